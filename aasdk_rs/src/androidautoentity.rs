@@ -0,0 +1,737 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+use openssl::x509::X509;
+
+use crate::channels::{ChannelHandler, ChannelRegistry};
+use crate::channels::control_service_channel::{self, ControlServiceChannel, ProtocolVersion, VoiceSessionType, HEAD_UNIT_VERSION};
+use crate::channels::input_service_channel;
+use crate::cryptor::{Cryptor, HeadUnitIdentity};
+use crate::error::{EntityError, MessengerError, ProtocolError};
+use crate::focus::{AudioFocusManager, NavigationFocus, NavigationFocusManager};
+use crate::input::{self, ButtonAction, TouchPoint, TouchScreen};
+use crate::messenger::{ChannelID, ControlMessageID, InputMessageID, Message, MessageType, Messenger};
+use crate::pinger::{PingAction, Pinger};
+use crate::protos::AudioFocusRequestMessage::AudioFocusRequest;
+use crate::protos::AudioFocusStateEnum::audio_focus_state;
+use crate::protos::BindingRequestMessage::BindingRequest;
+use crate::protos::ButtonCodeEnum::button_code;
+use crate::protos::NavigationFocusRequestMessage::NavigationFocusRequest;
+use crate::protos::PingRequestMessage::PingRequest;
+use crate::protos::PingResponseMessage::PingResponse;
+use crate::protos::ServiceDiscoveryResponseMessage::ServiceDiscoveryResponse;
+use crate::protos::ShutdownReasonEnum::shutdown_reason;
+use crate::protos::ShutdownRequestMessage::ShutdownRequest;
+use crate::protos::StatusEnum::status;
+use crate::protos::TouchEventData::TouchEvent;
+use crate::protos::VersionResponseStatusEnum::version_response_status;
+use crate::protos::VoiceSessionRequestMessage::VoiceSessionRequest;
+use crate::services::{Service, ServiceRegistry};
+use crate::transport::Transport;
+
+///Which side of the car the steering wheel is on
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DriverPosition {
+    Left,
+    Right,
+}
+
+///Struct representing the head unit and car details reported to the phone during service discovery
+#[derive(Clone, Debug)]
+pub struct HeadUnitConfig {
+    pub head_unit_name: String,
+    pub car_model: String,
+    pub car_year: String,
+    pub car_serial: String,
+    pub driver_position: DriverPosition,
+    pub headunit_manufacturer: String,
+    pub headunit_model: String,
+    pub sw_build: String,
+    pub sw_version: String,
+    pub can_play_native_media_during_vr: bool,
+    pub hide_clock: bool,
+}
+
+impl Default for HeadUnitConfig {
+    fn default() -> Self {
+        HeadUnitConfig {
+            head_unit_name: "rustyauto".to_string(),
+            car_model: "Universal".to_string(),
+            car_year: "2022".to_string(),
+            car_serial: "20221017".to_string(),
+            driver_position: DriverPosition::Left,
+            headunit_manufacturer: "rustyauto".to_string(),
+            headunit_model: "rustyauto head unit".to_string(),
+            sw_build: "1".to_string(),
+            sw_version: env!("CARGO_PKG_VERSION").to_string(),
+            can_play_native_media_during_vr: false,
+            hide_clock: false,
+        }
+    }
+}
+
+///Session events reported to the head unit application
+#[derive(Clone, Debug, PartialEq)]
+pub enum AndroidAutoEvent {
+    VersionNegotiated(ProtocolVersion),
+    Disconnected(DisconnectReason),
+    ///Audio focus of the phone changed, because it asked for it or the head unit took it away
+    AudioFocusChanged(audio_focus_state::Enum),
+    ///Navigation focus moved between the phone and the native navigation
+    NavigationFocusChanged(NavigationFocus),
+    ///The Assistant became active, the head unit should duck its own media and open the microphone
+    VoiceSessionStarted,
+    ///The Assistant is done, the head unit can restore its media and close the microphone
+    ///
+    ///Audio sinks and the microphone source are told through `voice_session_changed` as well.
+    VoiceSessionStopped,
+    ///The phone asked for the keycodes it wants to receive, the binding failed if any of them is not supported
+    KeyBindingRequested(Vec<u32>),
+}
+
+///Why a session ended
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DisconnectReason {
+    ///The phone stopped answering pings while the link stayed up
+    Timeout,
+    ///Reading from or writing to the phone failed, e.g. because the cable was pulled
+    TransportError,
+    ///The phone does not speak the protocol version of the head unit
+    VersionMismatch,
+    ///The session failed otherwise, e.g. the TLS handshake with the phone
+    Error,
+    ///The head unit asked for the shutdown
+    Shutdown,
+    ///The phone asked for the shutdown
+    PhoneShutdown(shutdown_reason::Enum),
+}
+
+///Time the phone gets to acknowledge a shutdown request before the session is closed anyway
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+///Lets another thread end the session while `start` is running
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    shutdown_tx: Sender<shutdown_reason::Enum>,
+}
+
+impl ShutdownHandle {
+    pub fn request_shutdown(&self, reason: shutdown_reason::Enum) {
+        if self.shutdown_tx.send(reason).is_err() {
+            log::debug!("Session is already gone, nothing to shut down");
+        }
+    }
+}
+
+pub struct AndroidAutoEntity<T: Transport> {
+    messenger: Messenger<T>,
+    identity: Option<HeadUnitIdentity>,
+    config: HeadUnitConfig,
+    services: ServiceRegistry,
+    channels: ChannelRegistry,
+    negotiated_version: Option<ProtocolVersion>,
+    pinger: Pinger,
+    audio_focus: AudioFocusManager,
+    navigation_focus: NavigationFocusManager,
+    voice_session_active: bool,
+    ///Channel of the input service, known after service discovery
+    input_channel: Option<ChannelID>,
+    ///Touch screen advertised in the input channel, known after service discovery
+    touch_screen: Option<TouchScreen>,
+    ///Resolution of the head unit's touch panel, the advertised touch screen resolution if not set
+    touch_panel_size: Option<(u32, u32)>,
+    ///Keycodes advertised in the input channel
+    supported_keycodes: Vec<u32>,
+    ///Keycodes the phone bound in the current session
+    key_bindings: Vec<u32>,
+    session_active: bool,
+    shutdown_deadline: Option<Instant>,
+    shutdown_tx: Sender<shutdown_reason::Enum>,
+    shutdown_rx: Receiver<shutdown_reason::Enum>,
+    event_tx: Option<Sender<AndroidAutoEvent>>,
+    out_tx: Sender<Message>,
+    out_rx: Receiver<Message>,
+}
+
+impl<T: Transport> AndroidAutoEntity<T> {
+    pub fn new(transport: T) -> Self {
+        let (out_tx, out_rx) = channel();
+        let (shutdown_tx, shutdown_rx) = channel();
+        let mut channels = ChannelRegistry::new(out_tx.clone());
+        channels.register(ChannelID::Control, Box::new(ControlServiceChannel));
+        AndroidAutoEntity {
+            messenger: Messenger::init(transport),
+            identity: None,
+            config: HeadUnitConfig::default(),
+            services: ServiceRegistry::new(),
+            channels,
+            negotiated_version: None,
+            pinger: Pinger::default(),
+            audio_focus: AudioFocusManager::new(),
+            navigation_focus: NavigationFocusManager::new(),
+            voice_session_active: false,
+            input_channel: None,
+            touch_screen: None,
+            touch_panel_size: None,
+            supported_keycodes: Vec::new(),
+            key_bindings: Vec::new(),
+            session_active: false,
+            shutdown_deadline: None,
+            shutdown_tx,
+            shutdown_rx,
+            event_tx: None,
+            out_tx,
+            out_rx,
+        }
+    }
+
+    ///Certificate and key used to authenticate the head unit towards the phone
+    pub fn set_identity(&mut self, identity: HeadUnitIdentity) {
+        log::info!("Using head unit certificate {:?}", identity.certificate().subject_name());
+        self.identity = Some(identity);
+    }
+
+    pub fn set_config(&mut self, config: HeadUnitConfig) {
+        self.config = config;
+    }
+
+    ///Register a service to be advertised to the phone, its channel id is assigned during service discovery
+    pub fn add_service(&mut self, service: Box<dyn Service>) {
+        self.services.add(service);
+    }
+
+    ///Channel ids the services got in the last service discovery, in the order they were added
+    ///
+    ///None for services added after the discovery or that did not get an id.
+    pub fn channel_ids(&self) -> Vec<Option<ChannelID>> {
+        self.services.channel_ids()
+    }
+
+    ///Handle a channel with a custom implementation, replacing the one of its service if there is any
+    ///
+    ///Channel ids registered before service discovery are not assigned to any service.
+    pub fn register_channel(&mut self, channel_id: ChannelID, handler: Box<dyn ChannelHandler>) -> Option<Box<dyn ChannelHandler>> {
+        self.channels.register(channel_id, handler)
+    }
+
+    ///Stop handling a channel, its messages are dropped from now on
+    pub fn remove_channel(&mut self, channel_id: ChannelID) -> Option<Box<dyn ChannelHandler>> {
+        self.channels.remove(channel_id)
+    }
+
+    ///Receive session events on the returned channel
+    pub fn subscribe(&mut self) -> Receiver<AndroidAutoEvent> {
+        let (event_tx, event_rx) = channel();
+        self.event_tx = Some(event_tx);
+        event_rx
+    }
+
+    ///How often the head unit pings the phone, and how long it waits for an answer before giving up
+    pub fn set_keep_alive(&mut self, interval: Duration, timeout: Duration) {
+        self.pinger = Pinger::new(interval, timeout);
+    }
+
+    ///Round trip time of the last ping the phone answered
+    pub fn round_trip_time(&self) -> Option<Duration> {
+        self.pinger.round_trip_time()
+    }
+
+    ///Audio focus state the phone currently has
+    pub fn audio_focus(&self) -> audio_focus_state::Enum {
+        self.audio_focus.phone_state()
+    }
+
+    ///Take the audio focus away from the phone, e.g. when the radio or a chime starts playing
+    ///
+    ///`loss` is the state reported to the phone, LOSS_TRANSIENT_CAN_DUCK lets it keep playing quietly.
+    pub fn revoke_audio_focus(&mut self, loss: audio_focus_state::Enum) -> Result<(), EntityError> {
+        let state = self.audio_focus.revoke(loss);
+        self.notify_audio_focus(state)
+    }
+
+    ///Give the audio focus back to the phone once the head unit is done
+    pub fn restore_audio_focus(&mut self) -> Result<(), EntityError> {
+        let state = self.audio_focus.restore();
+        self.notify_audio_focus(state)
+    }
+
+    fn notify_audio_focus(&mut self, state: Option<audio_focus_state::Enum>) -> Result<(), EntityError> {
+        if let Some(state) = state {
+            if self.messenger.is_authenticated() {
+                self.messenger.send_message(control_service_channel::create_audio_focus_response_message(state)?)?;
+            }
+            self.emit(AndroidAutoEvent::AudioFocusChanged(state));
+        }
+        Ok(())
+    }
+
+    ///Who currently shows turn-by-turn navigation
+    pub fn navigation_focus(&self) -> NavigationFocus {
+        self.navigation_focus.focus()
+    }
+
+    ///Grant or deny navigation focus to the phone, takes effect immediately
+    pub fn set_allow_projected_navigation(&mut self, allow: bool) -> Result<(), EntityError> {
+        let focus = self.navigation_focus.set_allow_projected(allow);
+        self.notify_navigation_focus(focus)
+    }
+
+    ///Native navigation starts guiding, take the focus away from the phone
+    pub fn take_navigation_focus(&mut self) -> Result<(), EntityError> {
+        let focus = self.navigation_focus.take();
+        self.notify_navigation_focus(focus)
+    }
+
+    ///Native navigation stopped, the phone gets the focus back if it asked for it
+    pub fn release_navigation_focus(&mut self) -> Result<(), EntityError> {
+        let focus = self.navigation_focus.release();
+        self.notify_navigation_focus(focus)
+    }
+
+    fn notify_navigation_focus(&mut self, focus: Option<NavigationFocus>) -> Result<(), EntityError> {
+        if let Some(focus) = focus {
+            if self.messenger.is_authenticated() {
+                self.messenger.send_message(control_service_channel::create_navigation_focus_response_message(focus)?)?;
+            }
+            self.emit(AndroidAutoEvent::NavigationFocusChanged(focus));
+        }
+        Ok(())
+    }
+
+    ///Whether the phone's Assistant is currently active
+    pub fn is_voice_session_active(&self) -> bool {
+        self.voice_session_active
+    }
+
+    fn set_voice_session_active(&mut self, active: bool) {
+        if self.voice_session_active == active {
+            return;
+        }
+        self.voice_session_active = active;
+        self.channels.notify_voice_session(active);
+        self.emit(if active { AndroidAutoEvent::VoiceSessionStarted } else { AndroidAutoEvent::VoiceSessionStopped });
+    }
+
+    ///Resolution of the head unit's touch panel, touch points are given in this resolution
+    ///
+    ///Without one, touch points are taken to be in the resolution of the touch screen the
+    ///input service advertises.
+    pub fn set_touch_panel_size(&mut self, width: u32, height: u32) {
+        self.touch_panel_size = Some((width, height));
+        if let Some(touch_screen) = &mut self.touch_screen {
+            touch_screen.set_panel_size(width, height);
+        }
+    }
+
+    ///A finger touches the panel
+    pub fn send_touch_down(&mut self, point: TouchPoint) -> Result<(), EntityError> {
+        let input_channel = self.input_channel()?;
+        let touch_event = self.touch_screen()?.down(point);
+        self.send_touch_event(input_channel, Some(touch_event))
+    }
+
+    ///Fingers moved on the panel, fingers that are not down are ignored
+    pub fn send_touch_move(&mut self, points: &[TouchPoint]) -> Result<(), EntityError> {
+        let input_channel = self.input_channel()?;
+        let touch_event = self.touch_screen()?.move_pointers(points);
+        self.send_touch_event(input_channel, touch_event)
+    }
+
+    ///A finger left the panel
+    pub fn send_touch_up(&mut self, pointer_id: u32) -> Result<(), EntityError> {
+        let input_channel = self.input_channel()?;
+        let touch_event = self.touch_screen()?.up(pointer_id);
+        self.send_touch_event(input_channel, touch_event)
+    }
+
+    ///A hardware button was pressed, held or released
+    pub fn send_button(&mut self, code: button_code::Enum, action: ButtonAction) -> Result<(), EntityError> {
+        let input_channel = self.input_channel()?;
+        self.check_keycode(input::keycode(code));
+        log::debug!("Button {:?} {:?}", code, action);
+        let message = input_service_channel::create_button_event_indication_message(input_channel, vec![input::button_event(code, action)])?;
+        self.messenger.send_message(message)?;
+        Ok(())
+    }
+
+    ///The rotary knob was turned by `delta` detents, positive is clockwise
+    pub fn send_rotary(&mut self, delta: i32) -> Result<(), EntityError> {
+        let input_channel = self.input_channel()?;
+        self.check_keycode(input::keycode(button_code::Enum::SCROLL_WHEEL));
+        log::debug!("Rotary knob turned by {}", delta);
+        let message = input_service_channel::create_relative_input_event_indication_message(input_channel, vec![input::rotary_event(delta)])?;
+        self.messenger.send_message(message)?;
+        Ok(())
+    }
+
+    ///Keycodes the phone asked for with its last binding request
+    pub fn key_bindings(&self) -> &[u32] {
+        &self.key_bindings
+    }
+
+    fn check_keycode(&self, keycode: u32) {
+        if !self.supported_keycodes.contains(&keycode) {
+            log::warn!("Keycode {:#x} was not advertised, the phone may ignore it", keycode);
+        }
+    }
+
+    fn input_channel(&self) -> Result<ChannelID, EntityError> {
+        self.input_channel.ok_or(EntityError::NoInputChannel)
+    }
+
+    fn touch_screen(&mut self) -> Result<&mut TouchScreen, EntityError> {
+        self.touch_screen.as_mut().ok_or(EntityError::NoInputChannel)
+    }
+
+    fn send_touch_event(&mut self, input_channel: ChannelID, touch_event: Option<TouchEvent>) -> Result<(), EntityError> {
+        if let Some(touch_event) = touch_event {
+            log::debug!("Touch event {:?}", touch_event.touch_action());
+            self.messenger.send_message(input_service_channel::create_touch_event_indication_message(input_channel, touch_event)?)?;
+        }
+        Ok(())
+    }
+
+    ///Request a shutdown from another thread
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle { shutdown_tx: self.shutdown_tx.clone() }
+    }
+
+    pub fn is_session_active(&self) -> bool {
+        self.session_active
+    }
+
+    ///Queue a message to be sent with the next `poll`
+    pub fn sender(&self) -> Sender<Message> {
+        self.out_tx.clone()
+    }
+
+    ///Certificate the head unit presented during the TLS handshake
+    pub fn presented_certificate(&self) -> Option<X509> {
+        self.messenger.cryptor().and_then(Cryptor::presented_certificate)
+    }
+
+    ///Certificate the phone presented during the TLS handshake
+    pub fn phone_certificate(&self) -> Option<X509> {
+        self.messenger.cryptor().and_then(Cryptor::peer_certificate)
+    }
+
+    ///Protocol version both sides agreed on, once the phone answered the version request
+    pub fn negotiated_version(&self) -> Option<ProtocolVersion> {
+        self.negotiated_version
+    }
+
+    ///Start the session and keep processing messages until it is shut down or fails
+    pub fn start(&mut self) -> Result<(), EntityError> {
+        self.start_session()?;
+        while self.session_active {
+            self.poll()?;
+        }
+        Ok(())
+    }
+
+    ///Set up the cryptor and send the version request, the rest of the session is driven by `poll`
+    pub fn start_session(&mut self) -> Result<(), EntityError> {
+        log::info!("Starting Android Auto entity");
+        let identity = self.identity.as_ref().ok_or(EntityError::MissingIdentity)?;
+        identity.validate()?;
+        self.messenger.set_cryptor(Cryptor::with_identity(identity)?);
+        for service in self.services.iter() {
+            service.start();
+        }
+        self.session_active = true;
+        self.messenger.send_message(control_service_channel::create_version_request_message(HEAD_UNIT_VERSION))
+            .map_err(|e| self.fail_session(e.into()))
+    }
+
+    ///Ask the phone to end the session, it is closed once the phone acknowledges
+    ///
+    ///Before the TLS handshake is done the request cannot be sent, the session is closed right away.
+    pub fn request_shutdown(&mut self, reason: shutdown_reason::Enum) -> Result<(), EntityError> {
+        if !self.session_active || self.shutdown_deadline.is_some() {
+            return Ok(());
+        }
+        if !self.messenger.is_authenticated() {
+            self.end_session(DisconnectReason::Shutdown);
+            return Ok(());
+        }
+        self.messenger.send_message(control_service_channel::create_shutdown_request_message(reason)?)?;
+        self.shutdown_deadline = Some(Instant::now() + SHUTDOWN_TIMEOUT);
+        Ok(())
+    }
+
+    ///Send queued messages, then read once from the phone and handle what arrived
+    ///
+    ///Every error but a protocol error, which only affects a single message, ends the session
+    ///before it is returned.
+    pub fn poll(&mut self) -> Result<(), EntityError> {
+        self.poll_session().map_err(|e| self.fail_session(e))
+    }
+
+    fn fail_session(&mut self, error: EntityError) -> EntityError {
+        let reason = match &error {
+            EntityError::Protocol(_) | EntityError::Messenger(MessengerError::Protocol(_)) => return error,
+            EntityError::Messenger(MessengerError::Transport(_)) => DisconnectReason::TransportError,
+            EntityError::PingTimeout(_) => DisconnectReason::Timeout,
+            EntityError::VersionMismatch { .. } => DisconnectReason::VersionMismatch,
+            _ => DisconnectReason::Error,
+        };
+        if self.session_active {
+            self.end_session(reason);
+        }
+        error
+    }
+
+    fn poll_session(&mut self) -> Result<(), EntityError> {
+        if !self.session_active {
+            return Ok(());
+        }
+        self.channels.poll_all();
+        while let Ok(message_to_send) = self.out_rx.try_recv() {
+            self.messenger.send_message(message_to_send)?;
+        }
+        while let Ok(reason) = self.shutdown_rx.try_recv() {
+            self.request_shutdown(reason)?;
+        }
+        if self.shutdown_deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            log::warn!("Phone did not acknowledge the shutdown request");
+            self.end_session(DisconnectReason::Shutdown);
+        }
+        if !self.session_active {
+            return Ok(());
+        }
+        self.keep_alive()?;
+        for received_message in self.messenger.receive_messages()? {
+            let result = match received_message.channel_id {
+                //answers are encrypted, only the version response may come before the TLS handshake
+                _ if !self.messenger.is_authenticated() && !is_version_response(&received_message) => {
+                    Err(ProtocolError::NotAuthenticated(received_message.channel_id).into())
+                }
+                ChannelID::Control => self.handle_control_message(&received_message),
+                channel_id if Some(channel_id) == self.input_channel => self.handle_input_message(&received_message),
+                _ => self.channels.dispatch(&received_message).map_err(EntityError::from),
+            };
+            match result {
+                Err(EntityError::Protocol(e)) | Err(EntityError::Messenger(MessengerError::Protocol(e))) => {
+                    log::error!("Dropping message on channel {:?}: {}", received_message.channel_id, e);
+                }
+                result => result?,
+            }
+            if !self.session_active {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    ///Stop every service, close the channels and release the transport
+    fn end_session(&mut self, reason: DisconnectReason) {
+        log::info!("Ending session: {:?}", reason);
+        self.session_active = false;
+        self.shutdown_deadline = None;
+        self.pinger.stop();
+        self.set_voice_session_active(false);
+        self.input_channel = None;
+        self.touch_screen = None;
+        self.key_bindings.clear();
+        self.audio_focus = AudioFocusManager::new();
+        //the policy of the application outlives the session
+        let allow_projected_navigation = self.navigation_focus.allows_projected();
+        self.navigation_focus = NavigationFocusManager::new();
+        self.navigation_focus.set_allow_projected(allow_projected_navigation);
+        for service in self.services.iter() {
+            service.stop();
+        }
+        self.channels.close_all();
+        if let Err(e) = self.messenger.close() {
+            log::error!("Failed to release the transport: {}", e);
+        }
+        self.emit(AndroidAutoEvent::Disconnected(reason));
+    }
+
+    ///Binding requests are answered here, they decide which keys the application has to report
+    fn handle_input_message(&mut self, message: &Message) -> Result<(), EntityError> {
+        if message.frame_header.message_type != MessageType::Specific
+            || !matches!(message.typed_message_id(), Ok(InputMessageID::BINDING_REQUEST)) {
+            return Ok(self.channels.dispatch(message)?);
+        }
+        let (_, request) = message.decode::<InputMessageID, BindingRequest>()?;
+        let keycodes: Vec<u32> = request.scan_codes.iter().map(|scan_code| *scan_code as u32).collect();
+        let unsupported = input::unsupported_keycodes(&self.supported_keycodes, &keycodes);
+        let binding_status = if unsupported.is_empty() {
+            log::info!("Phone binds keycodes {:x?}", keycodes);
+            self.key_bindings = keycodes.clone();
+            status::Enum::OK
+        } else {
+            log::warn!("Phone asked for unsupported keycodes {:x?}", unsupported);
+            status::Enum::FAIL
+        };
+        self.messenger.send_message(input_service_channel::create_binding_response_message(message.channel_id, binding_status)?)?;
+        self.emit(AndroidAutoEvent::KeyBindingRequested(keycodes));
+        Ok(())
+    }
+
+    fn handle_control_message(&mut self, message: &Message) -> Result<(), EntityError> {
+        match message.typed_message_id::<ControlMessageID>()? {
+            ControlMessageID::VERSION_RESPONSE => self.handle_version_response(message),
+            ControlMessageID::SERVICE_DISCOVERY_REQUEST => self.handle_service_discovery_request(message),
+            ControlMessageID::PING_REQUEST => self.handle_ping_request(message),
+            ControlMessageID::PING_RESPONSE => self.handle_ping_response(message),
+            ControlMessageID::AUDIO_FOCUS_REQUEST => self.handle_audio_focus_request(message),
+            ControlMessageID::NAVIGATION_FOCUS_REQUEST => self.handle_navigation_focus_request(message),
+            ControlMessageID::VOICE_SESSION_REQUEST => self.handle_voice_session_request(message),
+            ControlMessageID::SHUTDOWN_REQUEST => self.handle_shutdown_request(message),
+            ControlMessageID::SHUTDOWN_RESPONSE => self.handle_shutdown_response(),
+            _ => Ok(self.channels.dispatch(message)?),
+        }
+    }
+
+    ///Pings are encrypted, so they only start once the TLS handshake is done
+    fn keep_alive(&mut self) -> Result<(), EntityError> {
+        let now = Instant::now();
+        if !self.pinger.is_running() {
+            if self.messenger.is_authenticated() {
+                self.pinger.start(now);
+            }
+            return Ok(());
+        }
+        match self.pinger.poll(now) {
+            PingAction::Idle => Ok(()),
+            PingAction::Send(timestamp) => {
+                self.messenger.send_message(control_service_channel::create_ping_request_message(timestamp)?)?;
+                Ok(())
+            }
+            PingAction::TimedOut => {
+                self.end_session(DisconnectReason::Timeout);
+                Err(EntityError::PingTimeout(self.pinger.timeout()))
+            }
+        }
+    }
+
+    fn handle_ping_request(&mut self, message: &Message) -> Result<(), EntityError> {
+        let (_, ping_request) = message.decode::<ControlMessageID, PingRequest>()?;
+        self.messenger.send_message(control_service_channel::create_ping_response_message(ping_request.timestamp())?)?;
+        Ok(())
+    }
+
+    fn handle_ping_response(&mut self, message: &Message) -> Result<(), EntityError> {
+        let (_, ping_response) = message.decode::<ControlMessageID, PingResponse>()?;
+        self.pinger.handle_response(ping_response.timestamp(), Instant::now());
+        Ok(())
+    }
+
+    fn handle_audio_focus_request(&mut self, message: &Message) -> Result<(), EntityError> {
+        let (_, audio_focus_request) = message.decode::<ControlMessageID, AudioFocusRequest>()?;
+        let previous_state = self.audio_focus.phone_state();
+        let state = self.audio_focus.handle_request(audio_focus_request.audio_focus_type());
+        self.messenger.send_message(control_service_channel::create_audio_focus_response_message(state)?)?;
+        if state != previous_state {
+            self.emit(AndroidAutoEvent::AudioFocusChanged(state));
+        }
+        Ok(())
+    }
+
+    fn handle_navigation_focus_request(&mut self, message: &Message) -> Result<(), EntityError> {
+        let (_, navigation_focus_request) = message.decode::<ControlMessageID, NavigationFocusRequest>()?;
+        let requested = NavigationFocus::try_from(navigation_focus_request.type_()).unwrap_or(NavigationFocus::Native);
+        let previous_focus = self.navigation_focus.focus();
+        let focus = self.navigation_focus.handle_request(requested);
+        self.messenger.send_message(control_service_channel::create_navigation_focus_response_message(focus)?)?;
+        if focus != previous_focus {
+            self.emit(AndroidAutoEvent::NavigationFocusChanged(focus));
+        }
+        Ok(())
+    }
+
+    fn handle_voice_session_request(&mut self, message: &Message) -> Result<(), EntityError> {
+        let (_, voice_session_request) = message.decode::<ControlMessageID, VoiceSessionRequest>()?;
+        match VoiceSessionType::try_from(voice_session_request.type_) {
+            Ok(VoiceSessionType::Start) => self.set_voice_session_active(true),
+            Ok(VoiceSessionType::Stop) => self.set_voice_session_active(false),
+            Err(()) => {}
+        }
+        Ok(())
+    }
+
+    fn handle_shutdown_request(&mut self, message: &Message) -> Result<(), EntityError> {
+        let (_, shutdown_request) = message.decode::<ControlMessageID, ShutdownRequest>()?;
+        log::info!("Phone requested shutdown, reason {:?}", shutdown_request.reason());
+        self.messenger.send_message(control_service_channel::create_shutdown_response_message()?)?;
+        self.end_session(DisconnectReason::PhoneShutdown(shutdown_request.reason()));
+        Ok(())
+    }
+
+    fn handle_shutdown_response(&mut self) -> Result<(), EntityError> {
+        if self.shutdown_deadline.is_none() {
+            log::warn!("Shutdown response without a shutdown request, ignoring it");
+            return Ok(());
+        }
+        self.end_session(DisconnectReason::Shutdown);
+        Ok(())
+    }
+
+    fn handle_version_response(&mut self, message: &Message) -> Result<(), EntityError> {
+        let version_response = control_service_channel::parse_version_response(message)?;
+        log::info!("Phone speaks protocol version {}, status {:?}", version_response.version, version_response.status);
+        if version_response.status != version_response_status::Enum::MATCH {
+            self.end_session(DisconnectReason::VersionMismatch);
+            return Err(EntityError::VersionMismatch { head_unit: HEAD_UNIT_VERSION, phone: version_response.version });
+        }
+        self.negotiated_version = Some(version_response.version);
+        self.emit(AndroidAutoEvent::VersionNegotiated(version_response.version));
+        self.messenger.start_handshake()?;
+        Ok(())
+    }
+
+    fn handle_service_discovery_request(&mut self, message: &Message) -> Result<(), EntityError> {
+        let request = control_service_channel::parse_service_discovery_request(message)?;
+        log::info!("Service discovery request from {} ({})", request.device_name(), request.device_brand());
+        let response = self.create_service_discovery_response();
+        self.messenger.send_message(control_service_channel::create_service_discovery_response_message(response)?)?;
+        Ok(())
+    }
+
+    ///Describe the head unit and every registered service, assigning the services their channel ids
+    pub fn create_service_discovery_response(&mut self) -> ServiceDiscoveryResponse {
+        let mut response = ServiceDiscoveryResponse::new();
+        response.set_head_unit_name(self.config.head_unit_name.clone());
+        response.set_car_model(self.config.car_model.clone());
+        response.set_car_year(self.config.car_year.clone());
+        response.set_car_serial(self.config.car_serial.clone());
+        response.set_left_hand_drive_vehicle(self.config.driver_position == DriverPosition::Left);
+        response.set_headunit_manufacturer(self.config.headunit_manufacturer.clone());
+        response.set_headunit_model(self.config.headunit_model.clone());
+        response.set_sw_build(self.config.sw_build.clone());
+        response.set_sw_version(self.config.sw_version.clone());
+        response.set_can_play_native_media_during_vr(self.config.can_play_native_media_during_vr);
+        response.set_hide_clock(self.config.hide_clock);
+        self.services.assign_channels(&mut response, &mut self.channels);
+        self.input_channel = None;
+        self.touch_screen = None;
+        self.supported_keycodes.clear();
+        if let Some(channel_descriptor) = response.channels.iter().find(|channel_descriptor| channel_descriptor.input_channel.is_some()) {
+            self.input_channel = Some(ChannelID::from(channel_descriptor.channel_id() as u8));
+            let touch_config = channel_descriptor.input_channel.touch_screen_config.clone().into_option();
+            let (panel_width, panel_height) = self.touch_panel_size
+                .or_else(|| touch_config.as_ref().map(|touch_config| (touch_config.width(), touch_config.height())))
+                //without a touch config points are passed on unscaled, the panel size does not matter
+                .unwrap_or((1, 1));
+            let mut touch_screen = TouchScreen::new(panel_width, panel_height);
+            touch_screen.set_touch_config(touch_config);
+            self.touch_screen = Some(touch_screen);
+            self.supported_keycodes = channel_descriptor.input_channel.supported_keycodes.clone();
+        }
+        response
+    }
+
+    fn emit(&self, event: AndroidAutoEvent) {
+        if let Some(event_tx) = &self.event_tx {
+            if event_tx.send(event).is_err() {
+                log::debug!("Nobody listens for session events anymore");
+            }
+        }
+    }
+}
+
+fn is_version_response(message: &Message) -> bool {
+    message.channel_id == ChannelID::Control && matches!(message.typed_message_id(), Ok(ControlMessageID::VERSION_RESPONSE))
+}
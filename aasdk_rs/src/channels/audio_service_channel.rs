@@ -0,0 +1,150 @@
+use crate::channels::av_channel::{create_setup_response_message, split_timestamp, MediaStream};
+use crate::channels::channel_handler::{ChannelContext, ChannelHandler};
+use crate::error::ProtocolError;
+use crate::media::audio::SharedAudioSink;
+use crate::messenger::{AVMessageID, Message};
+use crate::protos::AVChannelSetupRequestMessage::AVChannelSetupRequest;
+use crate::protos::AVChannelSetupStatusEnum::avchannel_setup_status;
+use crate::protos::AVChannelStartIndicationMessage::AVChannelStartIndication;
+use crate::protos::AudioConfigData::AudioConfig;
+use crate::protos::AudioTypeEnum::audio_type;
+
+///Audio messages the phone may send before it has to wait for an acknowledgement
+pub const DEFAULT_AUDIO_MAX_UNACKED: u32 = 1;
+
+pub fn audio_config(sample_rate: u32, bit_depth: u32, channel_count: u32) -> AudioConfig {
+    let mut audio_config = AudioConfig::new();
+    audio_config.set_sample_rate(sample_rate);
+    audio_config.set_bit_depth(bit_depth);
+    audio_config.set_channel_count(channel_count);
+    audio_config
+}
+
+///16 bit PCM, 48 kHz stereo for media and 16 kHz mono for speech and system sounds
+pub fn default_audio_config(audio_type: audio_type::Enum) -> AudioConfig {
+    match audio_type {
+        audio_type::Enum::MEDIA => audio_config(48000, 16, 2),
+        _ => audio_config(16000, 16, 1),
+    }
+}
+
+///Handler of an audio output channel: media, speech (navigation prompts and the Assistant) or
+///system sounds
+///
+///Negotiates the audio configuration, follows the streams started by the phone, hands the
+///samples to the sink and acknowledges them.
+pub struct AudioServiceChannel {
+    audio_type: audio_type::Enum,
+    config: AudioConfig,
+    stream: MediaStream,
+    sink: Option<SharedAudioSink>,
+}
+
+impl AudioServiceChannel {
+    pub fn new(audio_type: audio_type::Enum, config: AudioConfig, max_unacked: u32) -> Self {
+        AudioServiceChannel { audio_type, config, stream: MediaStream::new(max_unacked), sink: None }
+    }
+
+    ///Hand the received samples to `sink`, without a sink they are dropped
+    pub fn set_sink(&mut self, sink: SharedAudioSink) {
+        self.sink = Some(sink);
+    }
+
+    pub fn audio_type(&self) -> audio_type::Enum {
+        self.audio_type
+    }
+
+    pub fn config(&self) -> &AudioConfig {
+        &self.config
+    }
+
+    ///Session of the running stream, None while the phone is not playing anything
+    pub fn session(&self) -> Option<i32> {
+        self.stream.session()
+    }
+
+    fn handle_setup_request(&mut self, message: &Message, context: &ChannelContext) -> Result<(), ProtocolError> {
+        let (_, request) = message.decode::<AVMessageID, AVChannelSetupRequest>()?;
+        log::info!("{:?} audio setup request for config {}", self.audio_type, request.config_index());
+        //only a single configuration is advertised
+        let status = if request.config_index() == 0 {
+            avchannel_setup_status::Enum::OK
+        } else {
+            log::error!("Phone asked for unknown {:?} audio config {}", self.audio_type, request.config_index());
+            avchannel_setup_status::Enum::FAIL
+        };
+        context.send(create_setup_response_message(message.channel_id, status, self.stream.max_unacked())?);
+        Ok(())
+    }
+
+    fn handle_media(&mut self, message_id: AVMessageID, message: &Message, context: &ChannelContext) -> Result<(), ProtocolError> {
+        if self.stream.session().is_none() {
+            log::error!("{:?} audio without a running stream, dropping {} bytes", self.audio_type, message.body().len());
+            return Ok(());
+        }
+        let (timestamp, pcm) = split_timestamp(message_id, message.body())?;
+        log::debug!("{:?} audio of {} bytes at {:?}", self.audio_type, pcm.len(), timestamp);
+        if let Some(sink) = &self.sink {
+            if let Err(e) = sink.borrow_mut().write(timestamp, pcm) {
+                log::error!("{:?} audio sink failed: {}", self.audio_type, e);
+            }
+        }
+        if let Some(ack) = self.stream.received(message.channel_id)? {
+            context.send(ack);
+        }
+        Ok(())
+    }
+
+    fn start_stream(&mut self, indication: &AVChannelStartIndication) {
+        log::info!("{:?} audio stream started, session {} with config {}", self.audio_type, indication.session(), indication.config());
+        self.stop_stream();
+        self.stream.start(indication.session());
+        if let Some(sink) = &self.sink {
+            if let Err(e) = sink.borrow_mut().start(&self.config) {
+                log::error!("{:?} audio sink failed to start: {}", self.audio_type, e);
+            }
+        }
+    }
+
+    fn stop_stream(&mut self) {
+        if !self.stream.stop() {
+            return;
+        }
+        if let Some(sink) = &self.sink {
+            if let Err(e) = sink.borrow_mut().stop() {
+                log::error!("{:?} audio sink failed to stop: {}", self.audio_type, e);
+            }
+        }
+    }
+}
+
+impl ChannelHandler for AudioServiceChannel {
+    fn handle_message(&mut self, message: &Message, context: &ChannelContext) -> Result<(), ProtocolError> {
+        log::debug!("Received message in {:?} audio service channel: {:?}", self.audio_type, message.message_id());
+        let message_id = message.typed_message_id::<AVMessageID>()?;
+        match message_id {
+            AVMessageID::SETUP_REQUEST => self.handle_setup_request(message, context)?,
+            AVMessageID::START_INDICATION => {
+                let (_, indication) = message.decode::<AVMessageID, AVChannelStartIndication>()?;
+                self.start_stream(&indication);
+            }
+            AVMessageID::STOP_INDICATION => {
+                log::info!("{:?} audio stream stopped", self.audio_type);
+                self.stop_stream();
+            }
+            AVMessageID::AV_MEDIA_WITH_TIMESTAMP_INDICATION | AVMessageID::AV_MEDIA_INDICATION => self.handle_media(message_id, message, context)?,
+            _ => log::error!("message not handled: {:?}", message_id),
+        }
+        Ok(())
+    }
+
+    fn voice_session_changed(&mut self, active: bool, _context: &ChannelContext) {
+        if let Some(sink) = &self.sink {
+            sink.borrow_mut().voice_session_changed(active);
+        }
+    }
+
+    fn close(&mut self) {
+        self.stop_stream();
+    }
+}
@@ -0,0 +1,95 @@
+use crate::error::ProtocolError;
+use crate::messenger::{AVMessageID, ChannelID, EncryptionType, FrameHeader, FrameType, Message, MessageID, MessageType};
+use crate::protos::AVChannelSetupResponseMessage::AVChannelSetupResponse;
+use crate::protos::AVChannelSetupStatusEnum::avchannel_setup_status;
+use crate::protos::AVMediaAckIndicationMessage::AVMediaAckIndication;
+
+///Media stream of an audio or video channel, between the start and the stop indication
+///
+///The phone sends up to `max_unacked` media messages before it waits for an acknowledgement.
+pub struct MediaStream {
+    max_unacked: u32,
+    session: Option<i32>,
+    ///Media messages received since the last acknowledgement
+    unacked: u32,
+}
+
+impl MediaStream {
+    pub fn new(max_unacked: u32) -> Self {
+        MediaStream { max_unacked: max_unacked.max(1), session: None, unacked: 0 }
+    }
+
+    pub fn max_unacked(&self) -> u32 {
+        self.max_unacked
+    }
+
+    ///Session of the running stream, None while the phone is not streaming
+    pub fn session(&self) -> Option<i32> {
+        self.session
+    }
+
+    pub fn start(&mut self, session: i32) {
+        self.session = Some(session);
+        self.unacked = 0;
+    }
+
+    ///Returns whether a stream was running
+    pub fn stop(&mut self) -> bool {
+        self.unacked = 0;
+        self.session.take().is_some()
+    }
+
+    ///Count a received media message, returns the acknowledgement to send once the window is full
+    pub fn received(&mut self, channel_id: ChannelID) -> Result<Option<Message>, ProtocolError> {
+        let session = match self.session {
+            Some(session) => session,
+            None => return Ok(None),
+        };
+        self.unacked += 1;
+        if self.unacked < self.max_unacked {
+            return Ok(None);
+        }
+        let ack = create_media_ack_indication_message(channel_id, session, self.unacked)?;
+        self.unacked = 0;
+        Ok(Some(ack))
+    }
+}
+
+///Split a media message body into the presentation timestamp and the media data
+pub fn split_timestamp(message_id: AVMessageID, body: &[u8]) -> Result<(Option<u64>, &[u8]), ProtocolError> {
+    if message_id != AVMessageID::AV_MEDIA_WITH_TIMESTAMP_INDICATION {
+        return Ok((None, body));
+    }
+    if body.len() < 8 {
+        return Err(ProtocolError::MissingTimestamp);
+    }
+    let (timestamp, data) = body.split_at(8);
+    Ok((Some(u64::from_be_bytes(timestamp.try_into().unwrap())), data))
+}
+
+pub fn create_setup_response_message(channel_id: ChannelID, status: avchannel_setup_status::Enum, max_unacked: u32) -> Result<Message, ProtocolError> {
+    let mut setup_response = AVChannelSetupResponse::new();
+    setup_response.set_media_status(status);
+    setup_response.set_max_unacked(max_unacked);
+    setup_response.configs.push(0);
+    Message::from_proto(channel_id, EncryptionType::Encrypted, MessageType::Specific, AVMessageID::SETUP_RESPONSE, &setup_response)
+}
+
+pub fn create_media_ack_indication_message(channel_id: ChannelID, session: i32, value: u32) -> Result<Message, ProtocolError> {
+    let mut media_ack_indication = AVMediaAckIndication::new();
+    media_ack_indication.set_session(session);
+    media_ack_indication.set_value(value);
+    Message::from_proto(channel_id, EncryptionType::Encrypted, MessageType::Specific, AVMessageID::AV_MEDIA_ACK_INDICATION, &media_ack_indication)
+}
+
+///Media sent by the head unit, the body is the timestamp in microseconds followed by the data
+pub fn create_media_with_timestamp_indication_message(channel_id: ChannelID, timestamp: u64, data: &[u8]) -> Message {
+    let mut payload = AVMessageID::AV_MEDIA_WITH_TIMESTAMP_INDICATION.word().to_be_bytes().to_vec();
+    payload.extend(timestamp.to_be_bytes());
+    payload.extend(data);
+    Message {
+        frame_header: FrameHeader { encryption_type: EncryptionType::Encrypted, message_type: MessageType::Specific, frame_type: FrameType::Bulk },
+        channel_id,
+        payload,
+    }
+}
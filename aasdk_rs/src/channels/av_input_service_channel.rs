@@ -0,0 +1,175 @@
+use crate::channels::audio_service_channel::{audio_config, DEFAULT_AUDIO_MAX_UNACKED};
+use crate::channels::av_channel::{create_media_with_timestamp_indication_message, create_setup_response_message};
+use crate::channels::channel_handler::{ChannelContext, ChannelHandler};
+use crate::error::ProtocolError;
+use crate::media::audio::SharedAudioSource;
+use crate::messenger::{AVMessageID, ChannelID, EncryptionType, Message, MessageType};
+use crate::pinger::timestamp_micros;
+use crate::protos::AVChannelSetupRequestMessage::AVChannelSetupRequest;
+use crate::protos::AVChannelSetupStatusEnum::avchannel_setup_status;
+use crate::protos::AVInputOpenRequestMessage::AVInputOpenRequest;
+use crate::protos::AVInputOpenResponseMessage::AVInputOpenResponse;
+use crate::protos::AVMediaAckIndicationMessage::AVMediaAckIndication;
+use crate::protos::AudioConfigData::AudioConfig;
+
+///Session the head unit reports for the microphone stream
+const MICROPHONE_SESSION: i32 = 0;
+
+///16 kHz mono, 16 bit PCM
+pub fn default_microphone_config() -> AudioConfig {
+    audio_config(16000, 16, 1)
+}
+
+///Handler of the AV input (microphone) channel
+///
+///While the phone keeps the microphone open, every poll sends the samples the source has
+///available, as long as the phone acknowledged enough of the previous ones.
+pub struct AVInputServiceChannel {
+    config: AudioConfig,
+    source: Option<SharedAudioSource>,
+    ///Channel the phone opened the microphone on, the samples are sent there
+    open_channel: Option<ChannelID>,
+    max_unacked: u32,
+    ///Media messages sent since the last acknowledgement
+    unacked: u32,
+}
+
+impl AVInputServiceChannel {
+    pub fn new(config: AudioConfig) -> Self {
+        AVInputServiceChannel { config, source: None, open_channel: None, max_unacked: DEFAULT_AUDIO_MAX_UNACKED, unacked: 0 }
+    }
+
+    ///Take the microphone samples from `source`, without a source the phone gets no samples
+    pub fn set_source(&mut self, source: SharedAudioSource) {
+        self.source = Some(source);
+    }
+
+    pub fn config(&self) -> &AudioConfig {
+        &self.config
+    }
+
+    ///Whether the phone currently listens to the microphone
+    pub fn is_open(&self) -> bool {
+        self.open_channel.is_some()
+    }
+
+    ///Bytes of 20 ms of samples, the size of a single media message
+    fn chunk_size(&self) -> usize {
+        let frame_size = (self.config.channel_count() * self.config.bit_depth() / 8).max(1);
+        ((self.config.sample_rate() / 50).max(1) * frame_size) as usize
+    }
+
+    fn handle_setup_request(&mut self, message: &Message, context: &ChannelContext) -> Result<(), ProtocolError> {
+        let (_, request) = message.decode::<AVMessageID, AVChannelSetupRequest>()?;
+        log::info!("Microphone setup request for config {}", request.config_index());
+        //only a single configuration is advertised
+        let status = if request.config_index() == 0 {
+            avchannel_setup_status::Enum::OK
+        } else {
+            log::error!("Phone asked for unknown microphone config {}", request.config_index());
+            avchannel_setup_status::Enum::FAIL
+        };
+        context.send(create_setup_response_message(message.channel_id, status, self.max_unacked)?);
+        Ok(())
+    }
+
+    fn handle_open_request(&mut self, message: &Message, context: &ChannelContext) -> Result<(), ProtocolError> {
+        let (_, request) = message.decode::<AVMessageID, AVInputOpenRequest>()?;
+        log::info!("Microphone {} request, max unacked {}", if request.open() { "open" } else { "close" }, request.max_unacked());
+        if request.open() {
+            self.open_source(message.channel_id, request.max_unacked());
+        } else {
+            self.close_source();
+        }
+        context.send(create_av_input_open_response_message(message.channel_id, MICROPHONE_SESSION, 0)?);
+        Ok(())
+    }
+
+    fn open_source(&mut self, channel_id: ChannelID, max_unacked: i32) {
+        self.close_source();
+        self.max_unacked = (max_unacked.max(0) as u32).max(1);
+        self.unacked = 0;
+        self.open_channel = Some(channel_id);
+        if let Some(source) = &self.source {
+            if let Err(e) = source.borrow_mut().start(&self.config) {
+                log::error!("Microphone source failed to start: {}", e);
+            }
+        }
+    }
+
+    fn close_source(&mut self) {
+        self.unacked = 0;
+        if self.open_channel.take().is_none() {
+            return;
+        }
+        if let Some(source) = &self.source {
+            if let Err(e) = source.borrow_mut().stop() {
+                log::error!("Microphone source failed to stop: {}", e);
+            }
+        }
+    }
+
+    fn handle_media_ack(&mut self, message: &Message) -> Result<(), ProtocolError> {
+        let (_, ack) = message.decode::<AVMessageID, AVMediaAckIndication>()?;
+        log::debug!("Microphone ack for session {} with value {}", ack.session(), ack.value());
+        self.unacked = self.unacked.saturating_sub(ack.value().max(1));
+        Ok(())
+    }
+}
+
+impl Default for AVInputServiceChannel {
+    fn default() -> Self {
+        Self::new(default_microphone_config())
+    }
+}
+
+impl ChannelHandler for AVInputServiceChannel {
+    fn handle_message(&mut self, message: &Message, context: &ChannelContext) -> Result<(), ProtocolError> {
+        log::debug!("Received message in av input service channel: {:?}", message.message_id());
+        let message_id = message.typed_message_id::<AVMessageID>()?;
+        match message_id {
+            AVMessageID::SETUP_REQUEST => self.handle_setup_request(message, context)?,
+            AVMessageID::AV_INPUT_OPEN_REQUEST => self.handle_open_request(message, context)?,
+            AVMessageID::AV_MEDIA_ACK_INDICATION => self.handle_media_ack(message)?,
+            _ => log::error!("message not handled: {:?}", message_id),
+        }
+        Ok(())
+    }
+
+    fn poll(&mut self, context: &ChannelContext) {
+        let (channel_id, source) = match (self.open_channel, &self.source) {
+            (Some(channel_id), Some(source)) => (channel_id, source.clone()),
+            _ => return,
+        };
+        let mut buffer = vec![0; self.chunk_size()];
+        while self.unacked < self.max_unacked {
+            let read = match source.borrow_mut().read(&mut buffer) {
+                Ok(0) => return,
+                Ok(read) => read,
+                Err(e) => {
+                    log::error!("Microphone source failed: {}", e);
+                    return;
+                }
+            };
+            context.send(create_media_with_timestamp_indication_message(channel_id, timestamp_micros() as u64, &buffer[..read]));
+            self.unacked += 1;
+        }
+    }
+
+    fn voice_session_changed(&mut self, active: bool, _context: &ChannelContext) {
+        if let Some(source) = &self.source {
+            source.borrow_mut().voice_session_changed(active);
+        }
+    }
+
+    fn close(&mut self) {
+        self.close_source();
+    }
+}
+
+pub fn create_av_input_open_response_message(channel_id: ChannelID, session: i32, value: u32) -> Result<Message, ProtocolError> {
+    let mut av_input_open_response = AVInputOpenResponse::new();
+    av_input_open_response.set_session(session);
+    av_input_open_response.set_value(value);
+    Message::from_proto(channel_id, EncryptionType::Encrypted, MessageType::Specific, AVMessageID::AV_INPUT_OPEN_RESPONSE, &av_input_open_response)
+}
@@ -0,0 +1,153 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::Sender;
+
+use crate::error::ProtocolError;
+use crate::messenger::{ChannelID, ControlMessageID, EncryptionType, Message, MessageType};
+use crate::protos::ChannelOpenRequestMessage::ChannelOpenRequest;
+use crate::protos::ChannelOpenResponseMessage::ChannelOpenResponse;
+use crate::protos::StatusEnum::status;
+
+///What a channel handler gets to talk back to the phone
+pub struct ChannelContext {
+    sender: Sender<Message>,
+}
+
+impl ChannelContext {
+    pub fn new(sender: Sender<Message>) -> Self {
+        ChannelContext { sender }
+    }
+
+    ///Queue a message for the phone
+    pub fn send(&self, message: Message) {
+        if self.sender.send(message).is_err() {
+            log::error!("Messenger is gone, dropping outgoing message");
+        }
+    }
+}
+
+///Implementation of a single channel, e.g. video, an audio stream or a vendor extension
+pub trait ChannelHandler {
+    ///The phone asked to open the channel, returning false rejects the request
+    fn open(&mut self, request: &ChannelOpenRequest, context: &ChannelContext) -> bool {
+        let _ = (request, context);
+        true
+    }
+
+    ///Handle every other message received on the channel
+    fn handle_message(&mut self, message: &Message, context: &ChannelContext) -> Result<(), ProtocolError>;
+
+    ///Called on every poll of the session, lets the channel send on its own, e.g. microphone samples
+    fn poll(&mut self, context: &ChannelContext) {
+        let _ = context;
+    }
+
+    ///The phone started or stopped a voice session, e.g. to open the microphone for the Assistant
+    fn voice_session_changed(&mut self, active: bool, context: &ChannelContext) {
+        let _ = (active, context);
+    }
+
+    ///The channel is going away, because the session ended or the handler was replaced
+    fn close(&mut self) {}
+}
+
+///Channel handlers of a session, keyed by channel id
+pub struct ChannelRegistry {
+    handlers: HashMap<ChannelID, Box<dyn ChannelHandler>>,
+    ///Channels whose handler was installed for a service during service discovery
+    service_channels: HashSet<ChannelID>,
+    context: ChannelContext,
+}
+
+impl ChannelRegistry {
+    ///Empty registry, the handlers have to be registered by the application
+    pub fn new(sender: Sender<Message>) -> Self {
+        ChannelRegistry { handlers: HashMap::new(), service_channels: HashSet::new(), context: ChannelContext::new(sender) }
+    }
+
+    ///Add a handler for a channel, returning the handler it replaced
+    pub fn register(&mut self, channel_id: ChannelID, handler: Box<dyn ChannelHandler>) -> Option<Box<dyn ChannelHandler>> {
+        log::debug!("Registering handler for channel {:?}", channel_id);
+        self.service_channels.remove(&channel_id);
+        let mut replaced = self.handlers.insert(channel_id, handler);
+        if let Some(replaced) = replaced.as_mut() {
+            replaced.close();
+        }
+        replaced
+    }
+
+    ///Add the handler of a service, it is dropped again by `remove_service` unless replaced in between
+    pub fn register_service(&mut self, channel_id: ChannelID, handler: Box<dyn ChannelHandler>) {
+        self.register(channel_id, handler);
+        self.service_channels.insert(channel_id);
+    }
+
+    ///Disable a channel, messages on it are rejected from now on
+    pub fn remove(&mut self, channel_id: ChannelID) -> Option<Box<dyn ChannelHandler>> {
+        self.service_channels.remove(&channel_id);
+        let mut removed = self.handlers.remove(&channel_id);
+        if let Some(removed) = removed.as_mut() {
+            removed.close();
+        }
+        removed
+    }
+
+    ///Remove the handler `register_service` installed, a handler the application registered since is kept
+    pub fn remove_service(&mut self, channel_id: ChannelID) -> Option<Box<dyn ChannelHandler>> {
+        if self.service_channels.contains(&channel_id) {
+            self.remove(channel_id)
+        } else {
+            None
+        }
+    }
+
+    pub fn contains(&self, channel_id: ChannelID) -> bool {
+        self.handlers.contains_key(&channel_id)
+    }
+
+    ///Give every channel the chance to send on its own
+    pub fn poll_all(&mut self) {
+        for handler in self.handlers.values_mut() {
+            handler.poll(&self.context);
+        }
+    }
+
+    ///Tell every channel about a voice session starting or stopping
+    pub fn notify_voice_session(&mut self, active: bool) {
+        for handler in self.handlers.values_mut() {
+            handler.voice_session_changed(active, &self.context);
+        }
+    }
+
+    ///Close every channel, e.g. at the end of the session
+    pub fn close_all(&mut self) {
+        for handler in self.handlers.values_mut() {
+            handler.close();
+        }
+    }
+
+    ///Route a received message to the handler of its channel
+    ///
+    ///Channel open requests are answered here, the handler only decides whether to accept them.
+    pub fn dispatch(&mut self, message: &Message) -> Result<(), ProtocolError> {
+        log::debug!("Channel ID: {:?}", message.channel_id);
+        let handler = self.handlers.get_mut(&message.channel_id)
+            .ok_or(ProtocolError::UnknownChannel(message.channel_id.into()))?;
+        if message.frame_header.message_type == MessageType::Control
+            && message.channel_id != ChannelID::Control
+            && matches!(message.typed_message_id(), Ok(ControlMessageID::CHANNEL_OPEN_REQUEST)) {
+            let (_, request) = message.decode::<ControlMessageID, ChannelOpenRequest>()?;
+            log::info!("Channel open request for channel {:?} with priority {}", message.channel_id, request.priority());
+            let accepted = handler.open(&request, &self.context);
+            self.context.send(create_channel_open_response_message(message.channel_id, accepted)?);
+            return Ok(());
+        }
+        handler.handle_message(message, &self.context)
+    }
+}
+
+fn create_channel_open_response_message(channel_id: ChannelID, accepted: bool) -> Result<Message, ProtocolError> {
+    log::info!("Creating channel open response message for channel {:?}", channel_id);
+    let mut channel_open_response = ChannelOpenResponse::new();
+    channel_open_response.set_status(if accepted { status::Enum::OK } else { status::Enum::FAIL });
+    Message::from_proto(channel_id, EncryptionType::Encrypted, MessageType::Control, ControlMessageID::CHANNEL_OPEN_RESPONSE, &channel_open_response)
+}
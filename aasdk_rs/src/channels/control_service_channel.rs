@@ -0,0 +1,170 @@
+use crate::channels::channel_handler::{ChannelContext, ChannelHandler};
+use crate::error::ProtocolError;
+use crate::focus::NavigationFocus;
+use crate::messenger;
+use crate::messenger::{ChannelID, ControlMessageID, EncryptionType, FrameHeader, FrameType, Message, MessageID, MessageType};
+use protobuf::Enum as protoenum;
+
+///Android Auto protocol version, exchanged before anything else on the control channel
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ProtocolVersion {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl std::fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+///Protocol version implemented by this head unit
+pub const HEAD_UNIT_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 1 };
+
+///Type of a VOICE_SESSION_REQUEST, sent by the phone when the Assistant starts or stops listening
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum VoiceSessionType {
+    Start = 1,
+    Stop = 2,
+}
+
+impl TryFrom<u32> for VoiceSessionType {
+    type Error = ();
+
+    fn try_from(voice_session_type: u32) -> Result<Self, ()> {
+        match voice_session_type {
+            1 => Ok(VoiceSessionType::Start),
+            2 => Ok(VoiceSessionType::Stop),
+            _ => {
+                log::error!("Unknown voice session type {}", voice_session_type);
+                Err(())
+            }
+        }
+    }
+}
+
+///Content of a VERSION_RESPONSE, the version the phone speaks and whether it accepts ours
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct VersionResponse {
+    pub version: ProtocolVersion,
+    pub status: crate::protos::VersionResponseStatusEnum::version_response_status::Enum,
+}
+
+///Handler of the control channel
+pub struct ControlServiceChannel;
+
+impl ChannelHandler for ControlServiceChannel {
+    fn handle_message(&mut self, message: &Message, _context: &ChannelContext) -> Result<(), ProtocolError> {
+        handle_message(message)
+    }
+}
+
+pub fn handle_message(message: &Message) -> Result<(), ProtocolError> {
+    log::info!("Received message in control service channel: {:?}", message);
+    let message_id = message.typed_message_id::<ControlMessageID>()?;
+    log::info!("Message ID: {:?}", message_id);
+    match message_id {
+        ControlMessageID::SSL_HANDSHAKE | ControlMessageID::AUTH_COMPLETE => {
+            //the messenger owns the cryptor and consumes these before dispatching
+            log::debug!("TLS handshake message outside of the handshake, ignoring it");
+        }
+        _ => log::error!("message not handled: {:?}", message_id)
+    }
+    Ok(())
+}
+
+pub fn create_version_request_message(version: ProtocolVersion) -> Message {
+    log::info!("Creating version request message for version {}", version);
+    let frame_header = FrameHeader {
+        encryption_type: EncryptionType::Plain,
+        message_type: MessageType::Specific,
+        frame_type: FrameType::Bulk,
+    };
+    let mut payload = ControlMessageID::VERSION_REQUEST.word().to_be_bytes().to_vec();
+    payload.extend(version.major.to_be_bytes());
+    payload.extend(version.minor.to_be_bytes());
+    messenger::Message { frame_header, channel_id: ChannelID::Control, payload }
+}
+
+pub fn parse_version_response(message: &Message) -> Result<VersionResponse, ProtocolError> {
+    let data = message.body();
+    if data.len() < 6 {
+        return Err(ProtocolError::LengthMismatch { declared: 6, actual: data.len() });
+    }
+    let version = ProtocolVersion {
+        major: u16::from_be_bytes([data[0], data[1]]),
+        minor: u16::from_be_bytes([data[2], data[3]]),
+    };
+    let status_word = u16::from_be_bytes([data[4], data[5]]);
+    let status = crate::protos::VersionResponseStatusEnum::version_response_status::Enum::from_i32(status_word as i32)
+        .unwrap_or(crate::protos::VersionResponseStatusEnum::version_response_status::Enum::MISMATCH);
+    Ok(VersionResponse { version, status })
+}
+
+pub fn parse_service_discovery_request(message: &Message) -> Result<crate::protos::ServiceDiscoveryRequestMessage::ServiceDiscoveryRequest, ProtocolError> {
+    let (_, request) = message.decode::<ControlMessageID, _>()?;
+    Ok(request)
+}
+
+pub fn create_service_discovery_response_message(service_discovery_response: crate::protos::ServiceDiscoveryResponseMessage::ServiceDiscoveryResponse) -> Result<Message, ProtocolError> {
+    log::info!("Creating service discovery response message");
+    Message::from_proto(ChannelID::Control, EncryptionType::Encrypted, MessageType::Specific, ControlMessageID::SERVICE_DISCOVERY_RESPONSE, &service_discovery_response)
+}
+
+pub fn create_ssl_handshake_message(handshake_buffer: &[u8]) -> Message {
+    log::info!("Creating ssl handshake message");
+    let frame_header = FrameHeader {
+        encryption_type: EncryptionType::Plain,
+        message_type: MessageType::Specific,
+        frame_type: FrameType::Bulk,
+    };
+    let mut payload = ControlMessageID::SSL_HANDSHAKE.word().to_be_bytes().to_vec();
+    payload.extend_from_slice(handshake_buffer);
+    messenger::Message { frame_header, channel_id: ChannelID::Control, payload }
+}
+
+pub fn create_ping_request_message(timestamp: i64) -> Result<Message, ProtocolError> {
+    log::debug!("Creating ping request message");
+    let mut ping_request = crate::protos::PingRequestMessage::PingRequest::new();
+    ping_request.set_timestamp(timestamp);
+    Message::from_proto(ChannelID::Control, EncryptionType::Encrypted, MessageType::Specific, ControlMessageID::PING_REQUEST, &ping_request)
+}
+
+pub fn create_ping_response_message(timestamp: i64) -> Result<Message, ProtocolError> {
+    log::debug!("Creating ping response message");
+    let mut ping_response = crate::protos::PingResponseMessage::PingResponse::new();
+    ping_response.set_timestamp(timestamp);
+    Message::from_proto(ChannelID::Control, EncryptionType::Encrypted, MessageType::Specific, ControlMessageID::PING_RESPONSE, &ping_response)
+}
+
+pub fn create_shutdown_request_message(reason: crate::protos::ShutdownReasonEnum::shutdown_reason::Enum) -> Result<Message, ProtocolError> {
+    log::info!("Creating shutdown request message, reason {:?}", reason);
+    let mut shutdown_request = crate::protos::ShutdownRequestMessage::ShutdownRequest::new();
+    shutdown_request.set_reason(reason);
+    Message::from_proto(ChannelID::Control, EncryptionType::Encrypted, MessageType::Specific, ControlMessageID::SHUTDOWN_REQUEST, &shutdown_request)
+}
+
+pub fn create_shutdown_response_message() -> Result<Message, ProtocolError> {
+    log::info!("Creating shutdown response message");
+    let shutdown_response = crate::protos::ShutdownResponseMessage::ShutdownResponse::new();
+    Message::from_proto(ChannelID::Control, EncryptionType::Encrypted, MessageType::Specific, ControlMessageID::SHUTDOWN_RESPONSE, &shutdown_response)
+}
+
+pub fn create_audio_focus_response_message(audio_focus_state: crate::protos::AudioFocusStateEnum::audio_focus_state::Enum) -> Result<Message, ProtocolError> {
+    log::info!("Creating audio focus response message, state {:?}", audio_focus_state);
+    let mut audio_focus_response = crate::protos::AudioFocusResponseMessage::AudioFocusResponse::new();
+    audio_focus_response.set_audio_focus_state(audio_focus_state);
+    Message::from_proto(ChannelID::Control, EncryptionType::Encrypted, MessageType::Specific, ControlMessageID::AUDIO_FOCUS_RESPONSE, &audio_focus_response)
+}
+
+pub fn create_navigation_focus_response_message(navigation_focus: NavigationFocus) -> Result<Message, ProtocolError> {
+    log::info!("Creating navigation focus response message, focus {:?}", navigation_focus);
+    let mut navigation_focus_response = crate::protos::NavigationFocusResponseMessage::NavigationFocusResponse::new();
+    navigation_focus_response.set_type(navigation_focus.into());
+    Message::from_proto(ChannelID::Control, EncryptionType::Encrypted, MessageType::Specific, ControlMessageID::NAVIGATION_FOCUS_RESPONSE, &navigation_focus_response)
+}
+
+pub fn create_auth_complete_message(auth_complete_indication: crate::protos::AuthCompleteIndicationMessage::AuthCompleteIndication) -> Result<Message, ProtocolError> {
+    log::info!("Creating auth complete message");
+    Message::from_proto(ChannelID::Control, EncryptionType::Plain, MessageType::Specific, ControlMessageID::AUTH_COMPLETE, &auth_complete_indication)
+}
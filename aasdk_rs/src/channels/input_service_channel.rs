@@ -1,65 +1,57 @@
-use crate::messenger;
-use crate::messenger::{ChannelID, EncryptionType, FrameHeader, FrameType, Message, MessageType};
-use protobuf::Message as protomsg;
+use crate::channels::channel_handler::{ChannelContext, ChannelHandler};
+use crate::error::ProtocolError;
+use crate::messenger::{ChannelID, EncryptionType, InputMessageID, Message, MessageType};
+use crate::pinger::timestamp_micros;
+use crate::protos::BindingResponseMessage::BindingResponse;
+use crate::protos::ButtonEventData::ButtonEvent;
+use crate::protos::InputEventIndicationMessage::InputEventIndication;
+use crate::protos::RelativeInputEventData::RelativeInputEvent;
+use crate::protos::StatusEnum::status;
+use crate::protos::TouchEventData::TouchEvent;
 
-fn handle_channel_open_request(message: &Message) {
-    log::info!("Received channel open request for sensor_channel");
+///Handler of the input channel
+pub struct InputServiceChannel;
+
+impl ChannelHandler for InputServiceChannel {
+    fn handle_message(&mut self, message: &Message, _context: &ChannelContext) -> Result<(), ProtocolError> {
+        handle_message(message)
+    }
+}
+
+pub fn handle_message(message: &Message) -> Result<(), ProtocolError> {
+    log::info!("Received message in input service channel: {:?}", message);
+    let message_id = message.typed_message_id::<InputMessageID>()?;
+    log::info!("Message ID: {:?}", message_id);
+    log::error!("message not handled: {:?}", message_id);
+    Ok(())
+}
+
+pub fn create_touch_event_indication_message(channel_id: ChannelID, touch_event: TouchEvent) -> Result<Message, ProtocolError> {
+    let mut input_event_indication = input_event_indication();
+    input_event_indication.touch_event = Some(touch_event).into();
+    Message::from_proto(channel_id, EncryptionType::Encrypted, MessageType::Specific, InputMessageID::INPUT_EVENT_INDICATION, &input_event_indication)
+}
+
+pub fn create_button_event_indication_message(channel_id: ChannelID, button_events: Vec<ButtonEvent>) -> Result<Message, ProtocolError> {
+    let mut input_event_indication = input_event_indication();
+    input_event_indication.button_event.mut_or_insert_default().button_events = button_events;
+    Message::from_proto(channel_id, EncryptionType::Encrypted, MessageType::Specific, InputMessageID::INPUT_EVENT_INDICATION, &input_event_indication)
+}
+
+pub fn create_relative_input_event_indication_message(channel_id: ChannelID, relative_input_events: Vec<RelativeInputEvent>) -> Result<Message, ProtocolError> {
+    let mut input_event_indication = input_event_indication();
+    input_event_indication.relative_input_event.mut_or_insert_default().relative_input_events = relative_input_events;
+    Message::from_proto(channel_id, EncryptionType::Encrypted, MessageType::Specific, InputMessageID::INPUT_EVENT_INDICATION, &input_event_indication)
 }
 
-pub fn handle_message(message: &Message) {
-    log::info!("Received message in speech sensor service channel: {:?}", message);
-    let payload = message.clone().payload;
-    let message_id_word = u16::from_be_bytes([payload.as_slice()[0], payload.as_slice()[1]]);
-    //TODO: use word correctly
-    if message_id_word == crate::channels::control_service_channel::ControlMessageID::ChannelOpenRequest.into() {
-        handle_channel_open_request(message);
-    } else { unimplemented!() }
-    use protobuf::Enum as protoenum;
-    //let message_id = crate::protos::MediaAudioChannelMessageIdsEnum::avchannel_message::Enum::from_i32(message_id_word as i32);
-    log::info!("Message ID (raw): {:?}", message_id_word);
-    //let message_id = AVMessageID::try_from(message_id_word);
-    //log::info!("Message ID: {:?}", message_id);
-    /*match message_id {
-        Ok(AVMessageID::SetupRequest) => {
-            log::debug!("Setup request");
-            unimplemented!()
-            //handleAVChannelSetupRequest(payload, std:: move (eventHandler));
-        }
-        Ok(AVMessageID::AvInputOpenRequest) => {
-            log::debug!("AV input open request");
-            unimplemented!()
-            //this -> handleAVInputOpenRequest(payload, std:: move (eventHandler));
-        }
-        Ok(AVMessageID::AvMediaAckIndication) => {
-            log::debug!("AV media ack indication");
-            unimplemented!();
-            //this -> handleAVMediaAckIndication(payload, std:: move (eventHandler));
-        }
-        _ => {
-            match message_id_word {
-                7 => {
-                    //crate::channels::control_service_channel::ControlMessageID::ChannelOpenRequest::to_u16() => {
-                    log::debug!("Channel open request");
-                    //this -> handleChannelOpenRequest(payload, std:: move (eventHandler));
-                }
-                _ => log::error!("message not handled: {:?}", message_id)
-            }
-        }
-    }*/
+pub fn create_binding_response_message(channel_id: ChannelID, binding_status: status::Enum) -> Result<Message, ProtocolError> {
+    let mut binding_response = BindingResponse::new();
+    binding_response.set_status(binding_status);
+    Message::from_proto(channel_id, EncryptionType::Encrypted, MessageType::Specific, InputMessageID::BINDING_RESPONSE, &binding_response)
 }
 
-pub fn create_channel_open_response_message(channel_open_response_message: crate::protos::ChannelOpenResponseMessage::ChannelOpenResponse) -> Message {
-    log::info!("Creating channel open response message for sensor channel");
-    let frame_header = FrameHeader {
-        encryption_type: EncryptionType::Plain,
-        message_type: MessageType::Specific,
-        frame_type: FrameType::Bulk,
-    };
-    let mut payload = (crate::channels::control_service_channel::ControlMessageID::ChannelOpenRequest as u16).to_be_bytes().to_vec();
-    let mut bytes = channel_open_response_message.write_to_bytes().unwrap();
-    //println!("{:x?}", bytes);
-    payload.extend(bytes);
-    //println!("{:x?}", payload);
-    let message = messenger::Message { frame_header, channel_id: ChannelID::AVInput, payload };
-    message
+fn input_event_indication() -> InputEventIndication {
+    let mut input_event_indication = InputEventIndication::new();
+    input_event_indication.set_timestamp(timestamp_micros() as u64);
+    input_event_indication
 }
@@ -0,0 +1,10 @@
+pub mod av_channel;
+pub mod channel_handler;
+pub mod control_service_channel;
+pub mod audio_service_channel;
+pub mod av_input_service_channel;
+pub mod sensor_service_channel;
+pub mod video_service_channel;
+pub mod input_service_channel;
+
+pub use self::channel_handler::{ChannelContext, ChannelHandler, ChannelRegistry};
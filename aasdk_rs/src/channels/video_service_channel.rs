@@ -1,65 +1,182 @@
-use crate::messenger;
-use crate::messenger::{ChannelID, EncryptionType, FrameHeader, FrameType, Message, MessageType};
-use protobuf::Message as protomsg;
+use crate::channels::av_channel::{create_setup_response_message, split_timestamp, MediaStream};
+use crate::channels::channel_handler::{ChannelContext, ChannelHandler};
+use crate::error::ProtocolError;
+use crate::media::video::{is_codec_config, SharedVideoSink};
+use crate::messenger::{AVMessageID, ChannelID, EncryptionType, Message, MessageType};
+use crate::protos::AVChannelSetupRequestMessage::AVChannelSetupRequest;
+use crate::protos::AVChannelSetupStatusEnum::avchannel_setup_status;
+use crate::protos::AVChannelStartIndicationMessage::AVChannelStartIndication;
+use crate::protos::VideoConfigData::VideoConfig;
+use crate::protos::VideoFPSEnum::video_fps;
+use crate::protos::VideoFocusIndicationMessage::VideoFocusIndication;
+use crate::protos::VideoFocusModeEnum::video_focus_mode;
+use crate::protos::VideoFocusRequestMessage::VideoFocusRequest;
+use crate::protos::VideoResolutionEnum::video_resolution;
 
-fn handle_channel_open_request(message: &Message) {
-    log::info!("Received channel open request for sensor_channel");
+///Frames the phone may send before it has to wait for an acknowledgement
+pub const DEFAULT_VIDEO_MAX_UNACKED: u32 = 1;
+
+///Video configuration advertised when the application does not configure one, 800x480 at 30 fps
+pub fn default_video_config() -> VideoConfig {
+    let mut video_config = VideoConfig::new();
+    video_config.set_video_resolution(video_resolution::Enum::_480p);
+    video_config.set_video_fps(video_fps::Enum::_30);
+    video_config.set_margin_width(0);
+    video_config.set_margin_height(0);
+    video_config.set_dpi(140);
+    video_config
+}
+
+///Handler of the video channel
+///
+///Negotiates the video configuration, follows the stream started by the phone, answers focus
+///requests and acknowledges the received frames.
+pub struct VideoServiceChannel {
+    config: VideoConfig,
+    stream: MediaStream,
+    focus: video_focus_mode::Enum,
+    sink: Option<SharedVideoSink>,
 }
 
-pub fn handle_message(message: &Message) {
-    log::info!("Received message in speech sensor service channel: {:?}", message);
-    let payload = message.clone().payload;
-    let message_id_word = u16::from_be_bytes([payload.as_slice()[0], payload.as_slice()[1]]);
-    //TODO: use word correctly
-    if message_id_word == crate::channels::control_service_channel::ControlMessageID::ChannelOpenRequest.into() {
-        handle_channel_open_request(message);
-    } else { unimplemented!() }
-    use protobuf::Enum as protoenum;
-    //let message_id = crate::protos::MediaAudioChannelMessageIdsEnum::avchannel_message::Enum::from_i32(message_id_word as i32);
-    log::info!("Message ID (raw): {:?}", message_id_word);
-    //let message_id = AVMessageID::try_from(message_id_word);
-    //log::info!("Message ID: {:?}", message_id);
-    /*match message_id {
-        Ok(AVMessageID::SetupRequest) => {
-            log::debug!("Setup request");
-            unimplemented!()
-            //handleAVChannelSetupRequest(payload, std:: move (eventHandler));
+impl VideoServiceChannel {
+    pub fn new(config: VideoConfig, max_unacked: u32) -> Self {
+        VideoServiceChannel {
+            config,
+            stream: MediaStream::new(max_unacked),
+            focus: video_focus_mode::Enum::NONE,
+            sink: None,
+        }
+    }
+
+    ///Hand the received stream to `sink`, without a sink it is dropped
+    pub fn set_sink(&mut self, sink: SharedVideoSink) {
+        self.sink = Some(sink);
+    }
+
+    pub fn config(&self) -> &VideoConfig {
+        &self.config
+    }
+
+    ///Session of the running stream, None while the phone is not streaming
+    pub fn session(&self) -> Option<i32> {
+        self.stream.session()
+    }
+
+    pub fn focus(&self) -> video_focus_mode::Enum {
+        self.focus
+    }
+
+    fn handle_setup_request(&mut self, message: &Message, context: &ChannelContext) -> Result<(), ProtocolError> {
+        let (_, request) = message.decode::<AVMessageID, AVChannelSetupRequest>()?;
+        log::info!("Video setup request for config {}", request.config_index());
+        //only a single configuration is advertised
+        let status = if request.config_index() == 0 {
+            avchannel_setup_status::Enum::OK
+        } else {
+            log::error!("Phone asked for unknown video config {}", request.config_index());
+            avchannel_setup_status::Enum::FAIL
+        };
+        context.send(create_setup_response_message(message.channel_id, status, self.stream.max_unacked())?);
+        if status == avchannel_setup_status::Enum::OK {
+            //the phone only starts streaming once it has the focus
+            self.focus = video_focus_mode::Enum::FOCUSED;
+            context.send(create_video_focus_indication_message(message.channel_id, self.focus, false)?);
+        }
+        Ok(())
+    }
+
+    fn handle_media(&mut self, message_id: AVMessageID, message: &Message, context: &ChannelContext) -> Result<(), ProtocolError> {
+        if self.stream.session().is_none() {
+            log::error!("Video data without a running stream, dropping {} bytes", message.body().len());
+            return Ok(());
+        }
+        let (timestamp, data) = split_timestamp(message_id, message.body())?;
+        log::debug!("Video data of {} bytes at {:?}", data.len(), timestamp);
+        if let Some(sink) = &self.sink {
+            let mut sink = sink.borrow_mut();
+            //the parameter sets come without a timestamp ahead of the first frame
+            let result = if timestamp.is_none() && is_codec_config(data) {
+                sink.codec_config(data)
+            } else {
+                sink.frame(timestamp, data)
+            };
+            if let Err(e) = result {
+                log::error!("Video sink failed: {}", e);
+            }
+        }
+        if let Some(ack) = self.stream.received(message.channel_id)? {
+            context.send(ack);
+        }
+        Ok(())
+    }
+
+    fn start_stream(&mut self, indication: &AVChannelStartIndication) {
+        log::info!("Video stream started, session {} with config {}", indication.session(), indication.config());
+        self.stop_stream();
+        self.stream.start(indication.session());
+        if let Some(sink) = &self.sink {
+            if let Err(e) = sink.borrow_mut().start(&self.config) {
+                log::error!("Video sink failed to start: {}", e);
+            }
         }
-        Ok(AVMessageID::AvInputOpenRequest) => {
-            log::debug!("AV input open request");
-            unimplemented!()
-            //this -> handleAVInputOpenRequest(payload, std:: move (eventHandler));
+    }
+
+    fn stop_stream(&mut self) {
+        if !self.stream.stop() {
+            return;
         }
-        Ok(AVMessageID::AvMediaAckIndication) => {
-            log::debug!("AV media ack indication");
-            unimplemented!();
-            //this -> handleAVMediaAckIndication(payload, std:: move (eventHandler));
+        if let Some(sink) = &self.sink {
+            if let Err(e) = sink.borrow_mut().stop() {
+                log::error!("Video sink failed to stop: {}", e);
+            }
         }
-        _ => {
-            match message_id_word {
-                7 => {
-                    //crate::channels::control_service_channel::ControlMessageID::ChannelOpenRequest::to_u16() => {
-                    log::debug!("Channel open request");
-                    //this -> handleChannelOpenRequest(payload, std:: move (eventHandler));
-                }
-                _ => log::error!("message not handled: {:?}", message_id)
+    }
+}
+
+impl Default for VideoServiceChannel {
+    fn default() -> Self {
+        Self::new(default_video_config(), DEFAULT_VIDEO_MAX_UNACKED)
+    }
+}
+
+impl ChannelHandler for VideoServiceChannel {
+    fn handle_message(&mut self, message: &Message, context: &ChannelContext) -> Result<(), ProtocolError> {
+        log::debug!("Received message in video service channel: {:?}", message.message_id());
+        let message_id = message.typed_message_id::<AVMessageID>()?;
+        match message_id {
+            AVMessageID::SETUP_REQUEST => self.handle_setup_request(message, context)?,
+            AVMessageID::START_INDICATION => {
+                let (_, indication) = message.decode::<AVMessageID, AVChannelStartIndication>()?;
+                self.start_stream(&indication);
             }
+            AVMessageID::STOP_INDICATION => {
+                log::info!("Video stream stopped");
+                self.stop_stream();
+            }
+            AVMessageID::VIDEO_FOCUS_REQUEST => {
+                let (_, request) = message.decode::<AVMessageID, VideoFocusRequest>()?;
+                log::info!("Video focus request {:?} ({:?})", request.focus_mode(), request.focus_reason());
+                self.focus = match request.focus_mode() {
+                    video_focus_mode::Enum::UNFOCUSED => video_focus_mode::Enum::UNFOCUSED,
+                    _ => video_focus_mode::Enum::FOCUSED,
+                };
+                context.send(create_video_focus_indication_message(message.channel_id, self.focus, false)?);
+            }
+            AVMessageID::AV_MEDIA_WITH_TIMESTAMP_INDICATION | AVMessageID::AV_MEDIA_INDICATION => self.handle_media(message_id, message, context)?,
+            _ => log::error!("message not handled: {:?}", message_id),
         }
-    }*/
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.stop_stream();
+        self.focus = video_focus_mode::Enum::NONE;
+    }
 }
 
-pub fn create_channel_open_response_message(channel_open_response_message: crate::protos::ChannelOpenResponseMessage::ChannelOpenResponse) -> Message {
-    log::info!("Creating channel open response message for sensor channel");
-    let frame_header = FrameHeader {
-        encryption_type: EncryptionType::Plain,
-        message_type: MessageType::Specific,
-        frame_type: FrameType::Bulk,
-    };
-    let mut payload = (crate::channels::control_service_channel::ControlMessageID::ChannelOpenRequest as u16).to_be_bytes().to_vec();
-    let mut bytes = channel_open_response_message.write_to_bytes().unwrap();
-    //println!("{:x?}", bytes);
-    payload.extend(bytes);
-    //println!("{:x?}", payload);
-    let message = messenger::Message { frame_header, channel_id: ChannelID::AVInput, payload };
-    message
+pub fn create_video_focus_indication_message(channel_id: ChannelID, focus: video_focus_mode::Enum, unrequested: bool) -> Result<Message, ProtocolError> {
+    let mut video_focus_indication = VideoFocusIndication::new();
+    video_focus_indication.set_focus_mode(focus);
+    video_focus_indication.set_unrequested(unrequested);
+    Message::from_proto(channel_id, EncryptionType::Encrypted, MessageType::Specific, AVMessageID::VIDEO_FOCUS_INDICATION, &video_focus_indication)
 }
@@ -0,0 +1,214 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use openssl::asn1::Asn1Time;
+use openssl::pkey::{PKey, PKeyRef, Private};
+use openssl::ssl::{ErrorCode, Ssl, SslContext, SslMethod, SslStream, SslVerifyMode, SslVersion};
+use openssl::x509::{X509, X509Ref};
+
+#[derive(Debug, thiserror::Error)]
+pub enum CryptorError {
+    #[error("SSL error: {0}")]
+    Ssl(#[from] openssl::ssl::Error),
+    #[error("OpenSSL error: {0}")]
+    OpenSsl(#[from] openssl::error::ErrorStack),
+    #[error("TLS session is not established yet")]
+    HandshakeNotFinished,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum IdentityError {
+    #[error("Failed to read {path}: {source}")]
+    Io { path: PathBuf, source: std::io::Error },
+    #[error("Invalid head unit certificate: {0}")]
+    InvalidCertificate(openssl::error::ErrorStack),
+    #[error("Invalid head unit private key: {0}")]
+    InvalidPrivateKey(openssl::error::ErrorStack),
+    #[error("Head unit private key does not belong to the certificate")]
+    KeyMismatch,
+    #[error("Head unit certificate is not valid before {0}")]
+    NotYetValid(String),
+    #[error("Head unit certificate expired on {0}")]
+    Expired(String),
+}
+
+///Certificate and private key the head unit authenticates itself with
+#[derive(Clone)]
+pub struct HeadUnitIdentity {
+    certificate: X509,
+    private_key: PKey<Private>,
+}
+
+impl HeadUnitIdentity {
+    pub fn new(certificate: X509, private_key: PKey<Private>) -> Result<Self, IdentityError> {
+        let identity = HeadUnitIdentity { certificate, private_key };
+        identity.validate()?;
+        Ok(identity)
+    }
+
+    pub fn from_pem(certificate_pem: &[u8], private_key_pem: &[u8]) -> Result<Self, IdentityError> {
+        let certificate = X509::from_pem(certificate_pem).map_err(IdentityError::InvalidCertificate)?;
+        let private_key = PKey::private_key_from_pem(private_key_pem).map_err(IdentityError::InvalidPrivateKey)?;
+        Self::new(certificate, private_key)
+    }
+
+    pub fn from_pem_files(certificate_path: impl AsRef<Path>, private_key_path: impl AsRef<Path>) -> Result<Self, IdentityError> {
+        let read = |path: &Path| std::fs::read(path).map_err(|source| IdentityError::Io { path: path.to_path_buf(), source });
+        log::info!("Loading head unit certificate from {:?}", certificate_path.as_ref());
+        Self::from_pem(&read(certificate_path.as_ref())?, &read(private_key_path.as_ref())?)
+    }
+
+    ///Check that the key belongs to the certificate and that the certificate is currently valid
+    pub fn validate(&self) -> Result<(), IdentityError> {
+        let public_key = self.certificate.public_key().map_err(IdentityError::InvalidCertificate)?;
+        if !public_key.public_eq(&self.private_key) {
+            return Err(IdentityError::KeyMismatch);
+        }
+        let now = Asn1Time::days_from_now(0).map_err(IdentityError::InvalidCertificate)?;
+        if self.certificate.not_before() > now {
+            return Err(IdentityError::NotYetValid(self.certificate.not_before().to_string()));
+        }
+        if self.certificate.not_after() < now {
+            return Err(IdentityError::Expired(self.certificate.not_after().to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn certificate(&self) -> &X509Ref {
+        &self.certificate
+    }
+}
+
+///In-memory replacement for a socket BIO
+///
+///TLS records are never written to the transport directly, they are exchanged inside
+///SSL_HANDSHAKE control messages and Encrypted frames instead.
+#[derive(Default)]
+struct MemoryBio {
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+}
+
+impl Read for MemoryBio {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.incoming.is_empty() {
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
+        let size = buf.len().min(self.incoming.len());
+        buf[..size].copy_from_slice(&self.incoming[..size]);
+        self.incoming.drain(..size);
+        Ok(size)
+    }
+}
+
+impl Write for MemoryBio {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.outgoing.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+///TLS endpoint of the head unit
+///
+///The head unit acts as TLS client: `do_handshake` produces records to be sent to the phone
+///(`read_handshake_buffer`), the phone's answers are fed back with `write_handshake_buffer`
+///until the handshake is done. Afterwards all Encrypted frames go through `encrypt`/`decrypt`.
+pub struct Cryptor {
+    ssl_stream: SslStream<MemoryBio>,
+    active: bool,
+}
+
+impl Cryptor {
+    pub fn new(certificate: &X509Ref, private_key: &PKeyRef<Private>) -> Result<Self, CryptorError> {
+        let mut context_builder = SslContext::builder(SslMethod::tls_client())?;
+        context_builder.set_max_proto_version(Some(SslVersion::TLS1_2))?;
+        context_builder.set_certificate(certificate)?;
+        context_builder.set_private_key(private_key)?;
+        context_builder.check_private_key()?;
+        //the phone presents a self-signed certificate, there is nothing to verify it against
+        context_builder.set_verify(SslVerifyMode::NONE);
+        let mut ssl = Ssl::new(&context_builder.build())?;
+        ssl.set_connect_state();
+        let ssl_stream = SslStream::new(ssl, MemoryBio::default())?;
+        Ok(Cryptor { ssl_stream, active: false })
+    }
+
+    pub fn with_identity(identity: &HeadUnitIdentity) -> Result<Self, CryptorError> {
+        Self::new(&identity.certificate, &identity.private_key)
+    }
+
+    ///Advance the handshake as far as possible, returns true once it is finished
+    pub fn do_handshake(&mut self) -> Result<bool, CryptorError> {
+        match self.ssl_stream.do_handshake() {
+            Ok(()) => {
+                log::info!("TLS handshake finished, using {}", self.ssl_stream.ssl().version_str());
+                self.active = true;
+                Ok(true)
+            }
+            Err(e) if e.code() == ErrorCode::WANT_READ || e.code() == ErrorCode::WANT_WRITE => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    ///Certificate the head unit presented to the phone, once the handshake is finished
+    pub fn presented_certificate(&self) -> Option<X509> {
+        if !self.active {
+            return None;
+        }
+        self.ssl_stream.ssl().certificate().map(X509Ref::to_owned)
+    }
+
+    ///Certificate the phone presented to the head unit, once the handshake is finished
+    pub fn peer_certificate(&self) -> Option<X509> {
+        if !self.active {
+            return None;
+        }
+        self.ssl_stream.ssl().peer_certificate()
+    }
+
+    ///Take the TLS records that have to be sent to the phone
+    pub fn read_handshake_buffer(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.ssl_stream.get_mut().outgoing)
+    }
+
+    ///Feed TLS records received from the phone
+    pub fn write_handshake_buffer(&mut self, buffer: &[u8]) {
+        self.ssl_stream.get_mut().incoming.extend_from_slice(buffer);
+    }
+
+    pub fn encrypt(&mut self, plain_data: &[u8]) -> Result<Vec<u8>, CryptorError> {
+        if !self.active {
+            return Err(CryptorError::HandshakeNotFinished);
+        }
+        let mut written = 0;
+        while written < plain_data.len() {
+            written += self.ssl_stream.ssl_write(&plain_data[written..])?;
+        }
+        Ok(self.read_handshake_buffer())
+    }
+
+    pub fn decrypt(&mut self, encrypted_data: &[u8]) -> Result<Vec<u8>, CryptorError> {
+        if !self.active {
+            return Err(CryptorError::HandshakeNotFinished);
+        }
+        self.write_handshake_buffer(encrypted_data);
+        let mut plain_data = Vec::new();
+        let mut buffer = vec![0u8; 0x4000];
+        loop {
+            match self.ssl_stream.ssl_read(buffer.as_mut_slice()) {
+                Ok(size) => plain_data.extend_from_slice(&buffer[..size]),
+                Err(e) if e.code() == ErrorCode::WANT_READ || e.code() == ErrorCode::ZERO_RETURN => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(plain_data)
+    }
+}
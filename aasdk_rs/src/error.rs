@@ -0,0 +1,70 @@
+use crate::channels::control_service_channel::ProtocolVersion;
+use crate::cryptor::{CryptorError, IdentityError};
+use crate::messenger::{ChannelID, FrameType};
+use crate::transport::TransportError;
+
+///Errors caused by malformed or unexpected data from the phone, or by a message to it that
+///cannot be encoded
+///
+///A protocol error only affects the message it was raised for, the session keeps running.
+#[derive(Debug, thiserror::Error)]
+pub enum ProtocolError {
+    #[error("Frame header truncated, got {0} bytes")]
+    TruncatedHeader(usize),
+    #[error("Frame declares {declared} payload bytes but carries {actual}")]
+    LengthMismatch { declared: usize, actual: usize },
+    #[error("Unexpected {frame_type:?} frame on channel {channel_id:?}")]
+    UnexpectedFrame { channel_id: ChannelID, frame_type: FrameType },
+    #[error("No handler for channel {0}")]
+    UnknownChannel(u8),
+    #[error("Message payload too short to contain a message id")]
+    MissingMessageId,
+    #[error("Unknown message id {0:#06x}")]
+    UnknownMessageId(u16),
+    #[error("Media payload too short to contain a timestamp")]
+    MissingTimestamp,
+    #[error("Failed to decode protobuf message: {0}")]
+    Decode(#[from] protobuf::Error),
+    #[error("Failed to encode protobuf message: {0}")]
+    Encode(protobuf::Error),
+    #[error("Received an encrypted frame before the TLS session was set up")]
+    UnexpectedEncryption,
+    #[error("Message on channel {0:?} received before the TLS handshake finished")]
+    NotAuthenticated(ChannelID),
+    #[error("Failed to decrypt frame: {0}")]
+    Decrypt(#[from] CryptorError),
+}
+
+///Errors while sending or receiving a single message
+#[derive(Debug, thiserror::Error)]
+pub enum MessengerError {
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
+    #[error(transparent)]
+    Cryptor(#[from] CryptorError),
+    #[error("No cryptor set up for encrypted messages")]
+    NoCryptor,
+}
+
+///Errors that prevent an Android Auto session from starting or continuing
+#[derive(Debug, thiserror::Error)]
+pub enum EntityError {
+    #[error("No head unit certificate and private key configured")]
+    MissingIdentity,
+    #[error(transparent)]
+    Identity(#[from] IdentityError),
+    #[error(transparent)]
+    Cryptor(#[from] CryptorError),
+    #[error(transparent)]
+    Messenger(#[from] MessengerError),
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
+    #[error("No input channel was advertised to the phone")]
+    NoInputChannel,
+    #[error("Phone did not answer pings for {0:?}")]
+    PingTimeout(std::time::Duration),
+    #[error("Phone rejected protocol version {head_unit}, it speaks version {phone}")]
+    VersionMismatch { head_unit: ProtocolVersion, phone: ProtocolVersion },
+}
@@ -0,0 +1,426 @@
+use std::collections::{HashMap, HashSet};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use evdev::{AbsoluteAxisType, Device, InputEvent, InputEventKind, Key, RelativeAxisType, Synchronization};
+
+use crate::androidautoentity::AndroidAutoEntity;
+use crate::error::EntityError;
+use crate::input::{ButtonAction, TouchPoint};
+use crate::protos::ButtonCodeEnum::button_code;
+use crate::transport::Transport;
+
+///How long a reader thread waits for input before it checks whether it has to stop
+const READ_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+///Which button of the phone's UI an evdev key triggers
+#[derive(Clone)]
+pub struct KeyMap {
+    keys: HashMap<Key, button_code::Enum>,
+}
+
+impl KeyMap {
+    ///Map without any key, every key is ignored
+    pub fn new() -> Self {
+        KeyMap { keys: HashMap::new() }
+    }
+
+    ///Map `key` to `code`, returns the code it was mapped to before
+    pub fn insert(&mut self, key: Key, code: button_code::Enum) -> Option<button_code::Enum> {
+        self.keys.insert(key, code)
+    }
+
+    pub fn remove(&mut self, key: Key) -> Option<button_code::Enum> {
+        self.keys.remove(&key)
+    }
+
+    pub fn get(&self, key: Key) -> Option<button_code::Enum> {
+        self.keys.get(&key).copied()
+    }
+
+    ///Every button code a key is mapped to, to be advertised by the input service
+    pub fn button_codes(&self) -> Vec<button_code::Enum> {
+        let mut button_codes: Vec<button_code::Enum> = self.keys.values().copied().collect();
+        button_codes.sort_by_key(|code| crate::input::keycode(*code));
+        button_codes.dedup();
+        button_codes
+    }
+}
+
+///Keys usually found on steering wheels, media remotes and keyboards
+impl Default for KeyMap {
+    fn default() -> Self {
+        let mut key_map = KeyMap::new();
+        for (key, code) in [
+            (Key::KEY_HOMEPAGE, button_code::Enum::HOME),
+            (Key::KEY_HOME, button_code::Enum::HOME),
+            (Key::KEY_BACK, button_code::Enum::BACK),
+            (Key::KEY_ESC, button_code::Enum::BACK),
+            (Key::KEY_MENU, button_code::Enum::MENU),
+            (Key::KEY_UP, button_code::Enum::UP),
+            (Key::KEY_DOWN, button_code::Enum::DOWN),
+            (Key::KEY_LEFT, button_code::Enum::LEFT),
+            (Key::KEY_RIGHT, button_code::Enum::RIGHT),
+            (Key::KEY_ENTER, button_code::Enum::ENTER),
+            (Key::KEY_OK, button_code::Enum::ENTER),
+            (Key::KEY_PLAYPAUSE, button_code::Enum::TOGGLE_PLAY),
+            (Key::KEY_PLAY, button_code::Enum::PLAY),
+            (Key::KEY_PAUSE, button_code::Enum::PAUSE),
+            (Key::KEY_NEXTSONG, button_code::Enum::NEXT),
+            (Key::KEY_PREVIOUSSONG, button_code::Enum::PREV),
+            (Key::KEY_PHONE, button_code::Enum::PHONE),
+            (Key::KEY_VOICECOMMAND, button_code::Enum::MICROPHONE_1),
+            (Key::KEY_MEDIA, button_code::Enum::MEDIA),
+            (Key::KEY_RADIO, button_code::Enum::RADIO),
+        ] {
+            key_map.insert(key, code);
+        }
+        key_map
+    }
+}
+
+///Input of the head unit, in the form the entity sends it to the phone
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputAction {
+    TouchDown(TouchPoint),
+    TouchMove(Vec<TouchPoint>),
+    TouchUp(u32),
+    Button(button_code::Enum, ButtonAction),
+    Rotary(i32),
+}
+
+impl InputAction {
+    pub fn send<T: Transport>(self, entity: &mut AndroidAutoEntity<T>) -> Result<(), EntityError> {
+        match self {
+            InputAction::TouchDown(point) => entity.send_touch_down(point),
+            InputAction::TouchMove(points) => entity.send_touch_move(&points),
+            InputAction::TouchUp(pointer_id) => entity.send_touch_up(pointer_id),
+            InputAction::Button(code, action) => entity.send_button(code, action),
+            InputAction::Rotary(delta) => entity.send_rotary(delta),
+        }
+    }
+}
+
+///Contact of the multi-touch protocol, the pointer id of a finger is its slot
+#[derive(Clone, Default)]
+struct Slot {
+    tracking_id: Option<i32>,
+    ///Tracking id of the finger last reported down
+    reported_id: Option<i32>,
+    x: u32,
+    y: u32,
+    moved: bool,
+}
+
+///Turns the events of a single evdev device into input actions
+///
+///Touch panels are read with the multi-touch protocol B (slots), single-touch panels through
+///BTN_TOUCH and ABS_X/ABS_Y. Touches are reported once the device sends SYN_REPORT, keys and
+///REL_WHEEL/REL_DIAL right away. A key held until the kernel repeats it is a long press.
+pub struct EventMapper {
+    key_map: KeyMap,
+    slots: Vec<Slot>,
+    slot: usize,
+    ///Whether the device sent multi-touch events, ABS_X/ABS_Y and BTN_TOUCH are ignored then
+    multitouch: bool,
+    ///Minimum of the X and Y axes, the panel coordinates start there
+    origin: (i32, i32),
+    ///Keys reported as long press that are still held
+    long_pressed: HashSet<Key>,
+}
+
+impl EventMapper {
+    pub fn new(key_map: KeyMap) -> Self {
+        EventMapper { key_map, slots: vec![Slot::default()], slot: 0, multitouch: false, origin: (0, 0), long_pressed: HashSet::new() }
+    }
+
+    ///Minimum values of the X and Y axes reported by the device
+    pub fn set_origin(&mut self, x: i32, y: i32) {
+        self.origin = (x, y);
+    }
+
+    pub fn handle(&mut self, event: &InputEvent) -> Vec<InputAction> {
+        match event.kind() {
+            InputEventKind::Key(Key::BTN_TOUCH) => {
+                if !self.multitouch {
+                    self.slots[0].tracking_id = if event.value() != 0 { Some(0) } else { None };
+                }
+                Vec::new()
+            }
+            InputEventKind::Key(key) => self.handle_key(key, event.value()).into_iter().collect(),
+            InputEventKind::AbsAxis(axis) => {
+                self.handle_axis(axis, event.value());
+                Vec::new()
+            }
+            InputEventKind::RelAxis(RelativeAxisType::REL_WHEEL) | InputEventKind::RelAxis(RelativeAxisType::REL_DIAL) if event.value() != 0 => {
+                vec![InputAction::Rotary(event.value())]
+            }
+            InputEventKind::Synchronization(Synchronization::SYN_REPORT) => self.flush_touches(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn handle_key(&mut self, key: Key, value: i32) -> Option<InputAction> {
+        let code = match self.key_map.get(key) {
+            Some(code) => code,
+            None => {
+                log::debug!("Ignoring unmapped key {:?}", key);
+                return None;
+            }
+        };
+        let action = match value {
+            0 => {
+                self.long_pressed.remove(&key);
+                ButtonAction::Release
+            }
+            1 => ButtonAction::Press,
+            //the kernel repeats held keys, only the first repeat is reported
+            _ if self.long_pressed.insert(key) => ButtonAction::LongPress,
+            _ => return None,
+        };
+        Some(InputAction::Button(code, action))
+    }
+
+    fn handle_axis(&mut self, axis: AbsoluteAxisType, value: i32) {
+        match axis {
+            AbsoluteAxisType::ABS_MT_SLOT => {
+                self.multitouch = true;
+                self.slot = value.max(0) as usize;
+                if self.slots.len() <= self.slot {
+                    self.slots.resize(self.slot + 1, Slot::default());
+                }
+            }
+            AbsoluteAxisType::ABS_MT_TRACKING_ID => {
+                self.multitouch = true;
+                self.slots[self.slot].tracking_id = if value < 0 { None } else { Some(value) };
+            }
+            AbsoluteAxisType::ABS_MT_POSITION_X => {
+                self.multitouch = true;
+                self.set_x(self.slot, value);
+            }
+            AbsoluteAxisType::ABS_MT_POSITION_Y => {
+                self.multitouch = true;
+                self.set_y(self.slot, value);
+            }
+            AbsoluteAxisType::ABS_X if !self.multitouch => self.set_x(0, value),
+            AbsoluteAxisType::ABS_Y if !self.multitouch => self.set_y(0, value),
+            _ => {}
+        }
+    }
+
+    fn set_x(&mut self, slot: usize, value: i32) {
+        self.slots[slot].x = (value - self.origin.0).max(0) as u32;
+        self.slots[slot].moved = true;
+    }
+
+    fn set_y(&mut self, slot: usize, value: i32) {
+        self.slots[slot].y = (value - self.origin.1).max(0) as u32;
+        self.slots[slot].moved = true;
+    }
+
+    ///Fingers that moved are reported first, then the ones that left or touched the panel
+    fn flush_touches(&mut self) -> Vec<InputAction> {
+        let mut moved = Vec::new();
+        let mut changed = Vec::new();
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            let point = TouchPoint::new(index as u32, slot.x, slot.y);
+            match (slot.reported_id, slot.tracking_id) {
+                (None, Some(_)) => changed.push(InputAction::TouchDown(point)),
+                (Some(_), None) => changed.push(InputAction::TouchUp(point.pointer_id)),
+                //the slot got a new finger without the old one being lifted, e.g. after dropped events
+                (Some(reported_id), Some(tracking_id)) if reported_id != tracking_id => {
+                    changed.push(InputAction::TouchUp(point.pointer_id));
+                    changed.push(InputAction::TouchDown(point));
+                }
+                (Some(_), Some(_)) if slot.moved => moved.push(point),
+                _ => {}
+            }
+            slot.reported_id = slot.tracking_id;
+            slot.moved = false;
+        }
+        let mut actions = Vec::new();
+        if !moved.is_empty() {
+            actions.push(InputAction::TouchMove(moved));
+        }
+        actions.extend(changed);
+        actions
+    }
+}
+
+///Size of the touch panel of `device` in its own coordinates, None if it is no touch panel
+pub fn panel_size(device: &Device) -> Option<(u32, u32)> {
+    let (x_axis, y_axis) = touch_axes(device)?;
+    let abs_state = device.get_abs_state().ok()?;
+    let x = abs_state[x_axis.0 as usize];
+    let y = abs_state[y_axis.0 as usize];
+    Some(((x.maximum - x.minimum + 1).max(1) as u32, (y.maximum - y.minimum + 1).max(1) as u32))
+}
+
+fn touch_axes(device: &Device) -> Option<(AbsoluteAxisType, AbsoluteAxisType)> {
+    let axes = device.supported_absolute_axes()?;
+    if axes.contains(AbsoluteAxisType::ABS_MT_POSITION_X) && axes.contains(AbsoluteAxisType::ABS_MT_POSITION_Y) {
+        Some((AbsoluteAxisType::ABS_MT_POSITION_X, AbsoluteAxisType::ABS_MT_POSITION_Y))
+    } else if axes.contains(AbsoluteAxisType::ABS_X) && axes.contains(AbsoluteAxisType::ABS_Y) {
+        Some((AbsoluteAxisType::ABS_X, AbsoluteAxisType::ABS_Y))
+    } else {
+        None
+    }
+}
+
+///Feeds the touch panels and buttons of the head unit, read from `/dev/input/event*`, into the
+///input channel
+///
+///Every device is read by a thread of its own, `poll` sends what they read to the phone. It
+///has to be called next to the entity's `poll`, the panel size of the first touch panel should
+///be handed to the entity with `set_touch_panel_size`.
+///
+///Dropping it stops the threads and closes the devices, it waits for the threads to notice,
+///which takes up to `READ_POLL_INTERVAL`.
+pub struct EvdevInput {
+    key_map: KeyMap,
+    action_tx: Sender<InputAction>,
+    action_rx: Receiver<InputAction>,
+    panel_size: Option<(u32, u32)>,
+    ///Set on drop, the reader threads check it whenever their device was idle for a while
+    stopped: Arc<AtomicBool>,
+    reader_threads: Vec<JoinHandle<()>>,
+}
+
+impl EvdevInput {
+    pub fn new(key_map: KeyMap) -> Self {
+        let (action_tx, action_rx) = channel();
+        EvdevInput { key_map, action_tx, action_rx, panel_size: None, stopped: Arc::new(AtomicBool::new(false)), reader_threads: Vec::new() }
+    }
+
+    ///Open the device at `path`, e.g. `/dev/input/event0`, and start reading it
+    pub fn open(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let device = Device::open(path.as_ref())?;
+        log::info!("Reading input from {} ({})", path.as_ref().display(), device.name().unwrap_or("unnamed"));
+        self.add_device(device)
+    }
+
+    ///Start reading an already opened device, on a thread that runs until this is dropped
+    pub fn add_device(&mut self, device: Device) -> std::io::Result<()> {
+        let mut event_mapper = EventMapper::new(self.key_map.clone());
+        if let Some((x_axis, y_axis)) = touch_axes(&device) {
+            let abs_state = device.get_abs_state()?;
+            event_mapper.set_origin(abs_state[x_axis.0 as usize].minimum, abs_state[y_axis.0 as usize].minimum);
+            if self.panel_size.is_none() {
+                self.panel_size = panel_size(&device);
+            }
+        }
+        set_nonblocking(&device)?;
+        let action_tx = self.action_tx.clone();
+        let stopped = self.stopped.clone();
+        let reader_thread = std::thread::Builder::new()
+            .name(format!("evdev {}", device.name().unwrap_or("input")))
+            .spawn(move || read_events(device, event_mapper, action_tx, stopped))?;
+        self.reader_threads.push(reader_thread);
+        Ok(())
+    }
+
+    ///Size of the first touch panel opened, in its own coordinates
+    pub fn panel_size(&self) -> Option<(u32, u32)> {
+        self.panel_size
+    }
+
+    ///Input read since the last call, without sending it anywhere
+    pub fn try_recv(&self) -> Option<InputAction> {
+        self.action_rx.try_recv().ok()
+    }
+
+    ///Send the input read since the last call to the phone
+    ///
+    ///Input read before the phone opened the input channel is dropped.
+    pub fn poll<T: Transport>(&mut self, entity: &mut AndroidAutoEntity<T>) -> Result<(), EntityError> {
+        while let Some(action) = self.try_recv() {
+            match action.send(entity) {
+                Err(EntityError::NoInputChannel) => log::debug!("No input channel yet, dropping input"),
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for EvdevInput {
+    fn default() -> Self {
+        Self::new(KeyMap::default())
+    }
+}
+
+impl Drop for EvdevInput {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        for reader_thread in self.reader_threads.drain(..) {
+            if reader_thread.join().is_err() {
+                log::error!("Input reader thread panicked");
+            }
+        }
+    }
+}
+
+///The device is read without blocking, so that its thread can stop while the device is idle
+fn read_events(mut device: Device, mut event_mapper: EventMapper, action_tx: Sender<InputAction>, stopped: Arc<AtomicBool>) {
+    let name = device.name().unwrap_or("unnamed").to_string();
+    while !stopped.load(Ordering::Relaxed) {
+        match wait_readable(&device, READ_POLL_INTERVAL) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => {
+                log::error!("Waiting for input device {} failed: {}", name, e);
+                return;
+            }
+        }
+        let events: Vec<InputEvent> = match device.fetch_events() {
+            Ok(events) => events.collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+            Err(e) => {
+                log::error!("Reading input device {} failed: {}", name, e);
+                return;
+            }
+        };
+        for event in events {
+            for action in event_mapper.handle(&event) {
+                //the input was dropped
+                if action_tx.send(action).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+    log::debug!("Stopped reading input device {}", name);
+}
+
+fn set_nonblocking(device: &Device) -> std::io::Result<()> {
+    let fd = device.as_raw_fd();
+    //SAFETY: the descriptor belongs to `device`, which outlives both calls
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+///Whether `device` has input to read, false if nothing arrived within `timeout`
+fn wait_readable(device: &Device, timeout: Duration) -> std::io::Result<bool> {
+    let mut poll_fd = libc::pollfd { fd: device.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+    //SAFETY: `poll_fd` is a single valid entry and the descriptor belongs to `device`
+    match unsafe { libc::poll(&mut poll_fd, 1, timeout.as_millis() as libc::c_int) } {
+        0 => Ok(false),
+        result if result < 0 => {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(false);
+            }
+            Err(error)
+        }
+        //errors and hang-ups are reported by the read that follows
+        _ => Ok(true),
+    }
+}
@@ -0,0 +1,209 @@
+use crate::protos::AudioFocusStateEnum::audio_focus_state;
+use crate::protos::AudioFocusTypeEnum::audio_focus_type;
+
+///Arbitrates the audio focus between the phone and the head unit application
+///
+///The phone asks for focus with AUDIO_FOCUS_REQUEST and gets the resulting state in an
+///AUDIO_FOCUS_RESPONSE. The head unit can take the focus away at any time (car radio, reversing
+///chime, ...) by sending an unsolicited AUDIO_FOCUS_RESPONSE with a loss state.
+pub struct AudioFocusManager {
+    phone_state: audio_focus_state::Enum,
+    ///Set while the head unit application holds the focus
+    revoked: Option<audio_focus_state::Enum>,
+    ///What the phone had before the head unit took the focus
+    state_before_revoke: audio_focus_state::Enum,
+}
+
+impl AudioFocusManager {
+    pub fn new() -> Self {
+        AudioFocusManager {
+            phone_state: audio_focus_state::Enum::NONE,
+            revoked: None,
+            state_before_revoke: audio_focus_state::Enum::NONE,
+        }
+    }
+
+    ///Focus state the phone currently has
+    pub fn phone_state(&self) -> audio_focus_state::Enum {
+        self.phone_state
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.revoked.is_some()
+    }
+
+    ///Answer a focus request of the phone, returns the state to send back
+    pub fn handle_request(&mut self, focus_type: audio_focus_type::Enum) -> audio_focus_state::Enum {
+        let requested = match focus_type {
+            audio_focus_type::Enum::GAIN => audio_focus_state::Enum::GAIN,
+            audio_focus_type::Enum::GAIN_TRANSIENT => audio_focus_state::Enum::GAIN_TRANSIENT,
+            audio_focus_type::Enum::GAIN_NAVI => audio_focus_state::Enum::GAIN_TRANSIENT_GUIDANCE_ONLY,
+            audio_focus_type::Enum::RELEASE => audio_focus_state::Enum::LOSS,
+            audio_focus_type::Enum::NONE => return self.phone_state,
+        };
+        if self.revoked.is_some() {
+            //remembered for when the head unit gives the focus back
+            self.state_before_revoke = requested;
+        }
+        let granted = match self.revoked {
+            None => requested,
+            Some(_) if requested == audio_focus_state::Enum::LOSS => requested,
+            //guidance may still be mixed in while the head unit only ducks the phone
+            Some(audio_focus_state::Enum::LOSS_TRANSIENT_CAN_DUCK) if focus_type == audio_focus_type::Enum::GAIN_NAVI => requested,
+            Some(revoked) => {
+                log::info!("Head unit holds the audio focus, denying {:?}", focus_type);
+                revoked
+            }
+        };
+        log::info!("Audio focus request {:?}, answering {:?}", focus_type, granted);
+        self.phone_state = granted;
+        granted
+    }
+
+    ///Take the focus away from the phone, returns the state to notify the phone of
+    ///
+    ///`loss` is one of the LOSS states, e.g. LOSS for the radio, LOSS_TRANSIENT_CAN_DUCK for a chime.
+    pub fn revoke(&mut self, loss: audio_focus_state::Enum) -> Option<audio_focus_state::Enum> {
+        if self.revoked.is_none() {
+            self.state_before_revoke = self.phone_state;
+        }
+        self.revoked = Some(loss);
+        if self.phone_state == loss {
+            return None;
+        }
+        self.phone_state = loss;
+        Some(loss)
+    }
+
+    ///Give the focus back to the phone, returns the state to notify the phone of
+    ///
+    ///The phone only gets back what it had (or asked for) while the head unit held the focus.
+    pub fn restore(&mut self) -> Option<audio_focus_state::Enum> {
+        self.revoked.take()?;
+        let restored = match self.state_before_revoke {
+            audio_focus_state::Enum::GAIN
+            | audio_focus_state::Enum::GAIN_TRANSIENT
+            | audio_focus_state::Enum::GAIN_MEDIA_ONLY
+            | audio_focus_state::Enum::GAIN_TRANSIENT_GUIDANCE_ONLY => self.state_before_revoke,
+            _ => audio_focus_state::Enum::LOSS,
+        };
+        if self.phone_state == restored {
+            return None;
+        }
+        self.phone_state = restored;
+        Some(restored)
+    }
+}
+
+impl Default for AudioFocusManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+///Who shows turn-by-turn navigation, the type field of the navigation focus messages
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NavigationFocus {
+    ///The head unit's built-in navigation
+    Native = 1,
+    ///Navigation projected from the phone
+    Projected = 2,
+}
+
+impl From<NavigationFocus> for u32 {
+    fn from(navigation_focus: NavigationFocus) -> Self {
+        navigation_focus as u32
+    }
+}
+
+impl TryFrom<u32> for NavigationFocus {
+    type Error = ();
+
+    fn try_from(navigation_focus_type: u32) -> Result<Self, ()> {
+        match navigation_focus_type {
+            1 => Ok(NavigationFocus::Native),
+            2 => Ok(NavigationFocus::Projected),
+            _ => {
+                log::error!("Unknown navigation focus type {}", navigation_focus_type);
+                Err(())
+            }
+        }
+    }
+}
+
+///Decides whether the phone may project navigation next to the head unit's own navigation
+///
+///Every method returns the focus to notify the phone of, or None if nothing changed.
+pub struct NavigationFocusManager {
+    focus: NavigationFocus,
+    ///Policy of the head unit application, whether the phone gets the focus at all
+    allow_projected: bool,
+    ///Native navigation is guiding right now
+    native_active: bool,
+    ///The phone asked for the focus and did not give it up since
+    phone_wants_focus: bool,
+}
+
+impl NavigationFocusManager {
+    pub fn new() -> Self {
+        NavigationFocusManager {
+            focus: NavigationFocus::Native,
+            allow_projected: true,
+            native_active: false,
+            phone_wants_focus: false,
+        }
+    }
+
+    pub fn focus(&self) -> NavigationFocus {
+        self.focus
+    }
+
+    pub fn allows_projected(&self) -> bool {
+        self.allow_projected
+    }
+
+    fn can_project(&self) -> bool {
+        self.allow_projected && !self.native_active && self.phone_wants_focus
+    }
+
+    fn update(&mut self) -> Option<NavigationFocus> {
+        let focus = if self.can_project() { NavigationFocus::Projected } else { NavigationFocus::Native };
+        if focus == self.focus {
+            return None;
+        }
+        log::info!("Navigation focus changes to {:?}", focus);
+        self.focus = focus;
+        Some(focus)
+    }
+
+    ///Answer a focus request of the phone, the answer is sent even if nothing changed
+    pub fn handle_request(&mut self, requested: NavigationFocus) -> NavigationFocus {
+        self.phone_wants_focus = requested == NavigationFocus::Projected;
+        self.update();
+        self.focus
+    }
+
+    ///Grant or deny the focus to the phone from now on
+    pub fn set_allow_projected(&mut self, allow_projected: bool) -> Option<NavigationFocus> {
+        self.allow_projected = allow_projected;
+        self.update()
+    }
+
+    ///Native navigation starts guiding, the phone loses the focus
+    pub fn take(&mut self) -> Option<NavigationFocus> {
+        self.native_active = true;
+        self.update()
+    }
+
+    ///Native navigation stopped, the phone gets the focus back if it still wants it
+    pub fn release(&mut self) -> Option<NavigationFocus> {
+        self.native_active = false;
+        self.update()
+    }
+}
+
+impl Default for NavigationFocusManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,185 @@
+use protobuf::Enum;
+
+use crate::protos::ButtonCodeEnum::button_code;
+use crate::protos::ButtonEventData::ButtonEvent;
+use crate::protos::RelativeInputEventData::RelativeInputEvent;
+use crate::protos::TouchActionEnum::touch_action;
+use crate::protos::TouchConfigData::TouchConfig;
+use crate::protos::TouchEventData::TouchEvent;
+use crate::protos::TouchLocationData::TouchLocation;
+
+///A finger on the touch panel, in the panel's native coordinates
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TouchPoint {
+    pub pointer_id: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TouchPoint {
+    pub fn new(pointer_id: u32, x: u32, y: u32) -> Self {
+        TouchPoint { pointer_id, x, y }
+    }
+}
+
+///Turns the fingers on the head unit's touch panel into the touch events of the protocol
+///
+///Coordinates are scaled from the panel's resolution to the touch screen resolution advertised
+///to the phone. Every event carries all fingers currently down, the first finger down and the
+///last one up are reported as PRESS and RELEASE, any other as POINTER_DOWN and POINTER_UP.
+pub struct TouchScreen {
+    panel_width: u32,
+    panel_height: u32,
+    touch_config: Option<TouchConfig>,
+    ///Fingers currently down, in advertised coordinates
+    pointers: Vec<TouchLocation>,
+}
+
+impl TouchScreen {
+    pub fn new(panel_width: u32, panel_height: u32) -> Self {
+        TouchScreen { panel_width: panel_width.max(1), panel_height: panel_height.max(1), touch_config: None, pointers: Vec::new() }
+    }
+
+    ///Resolution of the touch panel events are reported in
+    pub fn set_panel_size(&mut self, panel_width: u32, panel_height: u32) {
+        self.panel_width = panel_width.max(1);
+        self.panel_height = panel_height.max(1);
+    }
+
+    ///Touch screen resolution advertised to the phone, without one events are not scaled
+    pub fn set_touch_config(&mut self, touch_config: Option<TouchConfig>) {
+        self.touch_config = touch_config;
+        self.pointers.clear();
+    }
+
+    pub fn touch_config(&self) -> Option<&TouchConfig> {
+        self.touch_config.as_ref()
+    }
+
+    ///Number of fingers currently down
+    pub fn pointer_count(&self) -> usize {
+        self.pointers.len()
+    }
+
+    ///Forget every finger, e.g. when the session ends
+    pub fn reset(&mut self) {
+        self.pointers.clear();
+    }
+
+    fn scale(&self, point: TouchPoint) -> TouchLocation {
+        let (x, y) = match &self.touch_config {
+            Some(touch_config) => (
+                scale_coordinate(point.x, self.panel_width, touch_config.width()),
+                scale_coordinate(point.y, self.panel_height, touch_config.height()),
+            ),
+            None => (point.x, point.y),
+        };
+        let mut touch_location = TouchLocation::new();
+        touch_location.set_x(x);
+        touch_location.set_y(y);
+        touch_location.set_pointer_id(point.pointer_id);
+        touch_location
+    }
+
+    fn index_of(&self, pointer_id: u32) -> Option<usize> {
+        self.pointers.iter().position(|pointer| pointer.pointer_id() == pointer_id)
+    }
+
+    fn event(&self, action: touch_action::Enum, action_index: Option<usize>) -> TouchEvent {
+        let mut touch_event = TouchEvent::new();
+        touch_event.touch_location = self.pointers.clone();
+        touch_event.set_touch_action(action);
+        if let Some(action_index) = action_index {
+            touch_event.set_action_index(action_index as u32);
+        }
+        touch_event
+    }
+
+    ///A finger touches the panel, a finger that is already down is moved instead
+    pub fn down(&mut self, point: TouchPoint) -> TouchEvent {
+        if self.index_of(point.pointer_id).is_some() {
+            log::debug!("Pointer {} is already down, moving it", point.pointer_id);
+            return self.move_pointers(&[point]).unwrap_or_else(|| self.event(touch_action::Enum::DRAG, None));
+        }
+        self.pointers.push(self.scale(point));
+        if self.pointers.len() == 1 {
+            self.event(touch_action::Enum::PRESS, Some(0))
+        } else {
+            self.event(touch_action::Enum::POINTER_DOWN, Some(self.pointers.len() - 1))
+        }
+    }
+
+    ///Fingers moved, all of them are reported in a single event
+    ///
+    ///Fingers that are not down are ignored, returns None if none of them is.
+    pub fn move_pointers(&mut self, points: &[TouchPoint]) -> Option<TouchEvent> {
+        let mut moved = false;
+        for point in points {
+            match self.index_of(point.pointer_id) {
+                Some(index) => {
+                    self.pointers[index] = self.scale(*point);
+                    moved = true;
+                }
+                None => log::debug!("Ignoring move of pointer {} which is not down", point.pointer_id),
+            }
+        }
+        if !moved {
+            return None;
+        }
+        Some(self.event(touch_action::Enum::DRAG, None))
+    }
+
+    ///A finger left the panel, returns None if it was not down
+    pub fn up(&mut self, pointer_id: u32) -> Option<TouchEvent> {
+        let index = self.index_of(pointer_id)?;
+        //the event still carries the finger that goes up
+        let touch_event = if self.pointers.len() == 1 {
+            self.event(touch_action::Enum::RELEASE, Some(0))
+        } else {
+            self.event(touch_action::Enum::POINTER_UP, Some(index))
+        };
+        self.pointers.remove(index);
+        Some(touch_event)
+    }
+}
+
+fn scale_coordinate(coordinate: u32, panel_size: u32, touch_size: u32) -> u32 {
+    let scaled = coordinate as u64 * touch_size as u64 / panel_size as u64;
+    scaled.min(touch_size.saturating_sub(1) as u64) as u32
+}
+
+///What happened to a hardware button
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ButtonAction {
+    Press,
+    ///The button is still held after the long press time of the head unit
+    LongPress,
+    Release,
+}
+
+pub fn button_event(code: button_code::Enum, action: ButtonAction) -> ButtonEvent {
+    let mut button_event = ButtonEvent::new();
+    button_event.set_scan_code(keycode(code));
+    button_event.set_is_pressed(action != ButtonAction::Release);
+    button_event.set_meta(0);
+    button_event.set_long_press(action == ButtonAction::LongPress);
+    button_event
+}
+
+///Rotary knob turned by `delta` detents, positive is clockwise
+pub fn rotary_event(delta: i32) -> RelativeInputEvent {
+    let mut relative_input_event = RelativeInputEvent::new();
+    relative_input_event.set_scan_code(keycode(button_code::Enum::SCROLL_WHEEL));
+    relative_input_event.set_delta(delta);
+    relative_input_event
+}
+
+///Keycode of a button as advertised in the input channel and used in the binding request
+pub fn keycode(code: button_code::Enum) -> u32 {
+    code.value() as u32
+}
+
+///Keycodes of a binding request that the head unit did not advertise
+pub fn unsupported_keycodes(supported_keycodes: &[u32], requested_keycodes: &[u32]) -> Vec<u32> {
+    requested_keycodes.iter().copied().filter(|keycode| !supported_keycodes.contains(keycode)).collect()
+}
@@ -2,12 +2,20 @@ extern crate core;
 
 mod constants;
 pub mod usbdriver;
+pub mod transport;
 pub mod messenger;
 pub mod channels;
 pub mod androidautoentity;
 pub mod cryptor;
+pub mod error;
+#[cfg(feature = "evdev")]
+pub mod evdev_input;
+pub mod focus;
+pub mod input;
+pub mod media;
+pub mod pinger;
 mod utils;
-mod services;
+pub mod services;
 
 /*pub mod messages {
     include!(concat!(env!("OUT_DIR"), "/aasdk.proto.messages.rs"));
@@ -41,8 +49,1620 @@ mod tests {
             payload: vec![1,2,3]
         };
         let message_as_bytes = message.clone().to_byte_vector();
-        assert_eq!(message_as_bytes, vec![3,15,0,4,0,1,2,3]);
-        assert_eq!(Message::from_data_frame(message_as_bytes.as_slice()), message);
+        //channel, flags, payload length and the payload
+        assert_eq!(message_as_bytes, vec![3,15,0,3,1,2,3]);
+        assert_eq!(Message::from_data_frame(message_as_bytes.as_slice()).unwrap(), message);
     }
 
+    #[test]
+    fn test_messenger_over_loopback() {
+        use std::sync::mpsc::channel;
+        use std::time::Duration;
+        use crate::channels::ChannelRegistry;
+        use crate::messenger::{LegacyMessenger, Messenger};
+        use crate::transport::Transport;
+        use crate::transport::loopback::LoopbackTransport;
+
+        let (head_unit, phone) = LoopbackTransport::pair();
+        let mut messenger = Messenger::init(head_unit);
+        let (_in_tx, in_rx) = channel();
+        let (out_tx, out_rx) = channel();
+        let message = Message {
+            frame_header: FrameHeader {
+                encryption_type: EncryptionType::Plain,
+                message_type: MessageType::Control,
+                frame_type: FrameType::Bulk
+            },
+            channel_id: ChannelID::Control,
+            payload: vec![0, 1, 0, 1, 0, 1]
+        };
+        let mut channels = ChannelRegistry::new(out_tx.clone());
+        channels.register(ChannelID::Control, Box::new(crate::channels::control_service_channel::ControlServiceChannel));
+        out_tx.send(message.clone()).unwrap();
+        messenger.run(&in_rx, &out_rx, &mut channels);
+
+        let mut frame = vec![0u8; 100];
+        let size = phone.read_buffer(frame.as_mut_slice(), Duration::from_millis(100)).unwrap();
+        assert_eq!(&frame[..size], &[0, 7, 0, 6, 0, 1, 0, 1, 0, 1]);
+
+        let mut phone_messenger = LegacyMessenger::init(phone);
+        phone_messenger.send_message(message.clone()).unwrap();
+        let head_unit = messenger.transport();
+        let size = head_unit.read_buffer(frame.as_mut_slice(), Duration::from_millis(100)).unwrap();
+        assert_eq!(Message::from_data_frame(&frame[..size]).unwrap(), message);
+    }
+
+    #[test]
+    fn test_messenger_over_tcp() {
+        use std::io::{Read, Write};
+        use std::net::{TcpListener, TcpStream};
+        use crate::messenger::LegacyMessenger;
+        use crate::transport::tcp::TcpTransport;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let phone = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(&[0, 7, 0, 2, 0, 5]).unwrap();
+            let mut frame = [0u8; 6];
+            stream.read_exact(&mut frame).unwrap();
+            frame
+        });
+
+        let mut messenger = LegacyMessenger::init(TcpTransport::accept(&listener).unwrap());
+        let received = messenger.receive_message(100).unwrap();
+        assert_eq!(received.channel_id, ChannelID::Control);
+        assert_eq!(received.payload, vec![0, 5]);
+        messenger.send_message(received).unwrap();
+        assert_eq!(phone.join().unwrap(), [0, 7, 0, 2, 0, 5]);
+    }
+
+    #[test]
+    fn test_frame_reassembly() {
+        use crate::messenger::Messenger;
+        use crate::transport::loopback::LoopbackTransport;
+
+        let (head_unit, _phone) = LoopbackTransport::pair();
+        let mut messenger = Messenger::init(head_unit);
+        // two bulk frames in one read, the second one split across reads
+        let received = messenger.receive_bytes(&[0, 3, 0, 2, 0xa, 0xb, 1, 3, 0, 3, 0xc]);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].payload, vec![0xa, 0xb]);
+        let received = messenger.receive_bytes(&[0xd, 0xe]);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].channel_id, ChannelID::Input);
+        assert_eq!(received[0].payload, vec![0xc, 0xd, 0xe]);
+
+        // video message fragmented into First/Middle/Last, interleaved with a bulk frame on another channel
+        let received = messenger.receive_bytes(&[
+            3, 1, 0, 2, 0, 0, 0, 5, 1, 2,
+            4, 3, 0, 1, 9,
+            3, 0, 0, 2, 3, 4,
+            3, 2, 0, 1, 5,
+        ]);
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].channel_id, ChannelID::MediaAudio);
+        assert_eq!(received[1].channel_id, ChannelID::Video);
+        assert_eq!(received[1].frame_header.frame_type, FrameType::Bulk);
+        assert_eq!(received[1].payload, vec![1, 2, 3, 4, 5]);
+
+        // declared total size does not match the fragments
+        let received = messenger.receive_bytes(&[3, 1, 0, 1, 0, 0, 0, 3, 1, 3, 2, 0, 1, 2]);
+        assert!(received.is_empty());
+    }
+
+    #[test]
+    fn test_message_fragmentation() {
+        use crate::messenger::Messenger;
+        use crate::transport::loopback::LoopbackTransport;
+
+        let payload: Vec<u8> = (0..70000u32).map(|i| i as u8).collect();
+        let message = Message {
+            frame_header: FrameHeader {
+                encryption_type: EncryptionType::Plain,
+                message_type: MessageType::Specific,
+                frame_type: FrameType::Bulk
+            },
+            channel_id: ChannelID::Control,
+            payload: payload.clone()
+        };
+        let frames = message.clone().to_frames(0x4000);
+        assert_eq!(frames.len(), 5);
+        assert_eq!(&frames[0][..8], &[0, 1, 0x40, 0, 0, 1, 0x11, 0x70]);
+        assert_eq!(frames[1][1], 0);
+        assert_eq!(&frames[4][..4], &[0, 2, 0x11, 0x70]);
+
+        let (head_unit, _phone) = LoopbackTransport::pair();
+        let mut messenger = Messenger::init(head_unit);
+        let received: Vec<Message> = frames.iter().flat_map(|frame| messenger.receive_bytes(frame)).collect();
+        assert_eq!(received, vec![message.clone()]);
+
+        let frames = message.to_frames(u16::MAX as usize);
+        assert_eq!(frames.len(), 2);
+        assert!(frames.iter().all(|frame| frame.len() <= u16::MAX as usize + 8));
+    }
+
+    #[test]
+    fn test_malformed_frames() {
+        use crate::error::ProtocolError;
+
+        assert!(matches!(Message::from_data_frame(&[0, 3]), Err(ProtocolError::TruncatedHeader(2))));
+        assert!(matches!(Message::from_data_frame(&[0, 3, 0, 5, 1]), Err(ProtocolError::LengthMismatch { declared: 5, actual: 1 })));
+        let message = Message::from_data_frame(&[0, 3, 0, 1, 1]).unwrap();
+        assert!(matches!(message.message_id(), Err(ProtocolError::MissingMessageId)));
+        //the error carries the channel byte as received, also for ids no service uses
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let mut channels = crate::channels::ChannelRegistry::new(sender);
+        for channel_id in [3, 42, 255] {
+            let message = Message::from_data_frame(&[channel_id, 3, 0, 2, 0, 1]).unwrap();
+            assert!(matches!(channels.dispatch(&message), Err(ProtocolError::UnknownChannel(id)) if id == channel_id));
+        }
+    }
+
+    fn test_identity() -> (openssl::x509::X509, openssl::pkey::PKey<openssl::pkey::Private>) {
+        use openssl::asn1::Asn1Time;
+        use openssl::hash::MessageDigest;
+        use openssl::pkey::PKey;
+        use openssl::rsa::Rsa;
+        use openssl::x509::{X509, X509NameBuilder};
+
+        let private_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "rustyauto test").unwrap();
+        let name = name.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&private_key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        builder.sign(&private_key, MessageDigest::sha256()).unwrap();
+        (builder.build(), private_key)
+    }
+
+    ///Specific message the phone sends on `channel_id`, `body` follows the message id
+    fn phone_message<I: crate::messenger::MessageID>(channel_id: ChannelID, message_id: I, body: &[u8]) -> Message {
+        let mut payload = message_id.word().to_be_bytes().to_vec();
+        payload.extend(body);
+        Message { frame_header: FrameHeader { encryption_type: EncryptionType::Encrypted, message_type: MessageType::Specific, frame_type: FrameType::Bulk }, channel_id, payload }
+    }
+
+    ///Memory BIO of the phone's end of the TLS session
+    #[derive(Default)]
+    struct PhoneBio { incoming: Vec<u8>, outgoing: Vec<u8> }
+    impl std::io::Read for PhoneBio {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.incoming.is_empty() { return Err(std::io::ErrorKind::WouldBlock.into()); }
+            let size = buf.len().min(self.incoming.len());
+            buf[..size].copy_from_slice(&self.incoming[..size]);
+            self.incoming.drain(..size);
+            Ok(size)
+        }
+    }
+    impl std::io::Write for PhoneBio {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.outgoing.extend_from_slice(buf); Ok(buf.len()) }
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    ///Phone at the other end of a loopback transport, it negotiates the version and TLS with the entity
+    struct TestPhone {
+        transport: crate::transport::loopback::LoopbackTransport,
+        tls: openssl::ssl::SslStream<PhoneBio>,
+    }
+
+    impl TestPhone {
+        ///Start the session of `entity` and poll it until the TLS handshake is done
+        fn connect(entity: &mut crate::androidautoentity::AndroidAutoEntity<crate::transport::loopback::LoopbackTransport>, transport: crate::transport::loopback::LoopbackTransport) -> Self {
+            use std::time::Duration;
+            use openssl::ssl::{ErrorCode, Ssl, SslContext, SslMethod, SslStream};
+            use crate::transport::Transport;
+
+            let (certificate, private_key) = test_identity();
+            let mut context = SslContext::builder(SslMethod::tls_server()).unwrap();
+            context.set_certificate(&certificate).unwrap();
+            context.set_private_key(&private_key).unwrap();
+            let mut ssl = Ssl::new(&context.build()).unwrap();
+            ssl.set_accept_state();
+            let mut phone = TestPhone { transport, tls: SslStream::new(ssl, PhoneBio::default()).unwrap() };
+
+            entity.start_session().unwrap();
+            assert_eq!(phone.receive_frame().message_id().unwrap(), 1);
+            phone.transport.send_buffer(&[0, 3, 0, 8, 0, 2, 0, 1, 0, 7, 0, 0], Duration::ZERO).unwrap();
+            entity.poll().unwrap();
+            loop {
+                let message = phone.receive_frame();
+                match message.message_id().unwrap() {
+                    3 => {
+                        phone.tls.get_mut().incoming.extend_from_slice(&message.payload[2..]);
+                        if let Err(e) = phone.tls.do_handshake() {
+                            assert_eq!(e.code(), ErrorCode::WANT_READ);
+                        }
+                        let mut payload = vec![0, 3];
+                        payload.append(&mut phone.tls.get_mut().outgoing);
+                        if payload.len() > 2 {
+                            let reply = Message { frame_header: message.frame_header, channel_id: ChannelID::Control, payload };
+                            phone.transport.send_buffer(reply.to_byte_vector().as_slice(), Duration::ZERO).unwrap();
+                            entity.poll().unwrap();
+                        }
+                    }
+                    4 => return phone,
+                    id => panic!("unexpected control message {id}"),
+                }
+            }
+        }
+
+        fn receive_frame(&self) -> Message {
+            use crate::transport::Transport;
+            let mut buffer = vec![0u8; 0x10000];
+            let size = self.transport.read_buffer(buffer.as_mut_slice(), std::time::Duration::from_millis(500)).unwrap();
+            Message::from_data_frame(&buffer[..size]).unwrap()
+        }
+
+        ///Next message from the head unit, decrypted, pings are skipped
+        fn receive(&mut self) -> Message {
+            use crate::messenger::ControlMessageID;
+            loop {
+                let mut message = self.receive_frame();
+                if message.frame_header.encryption_type == EncryptionType::Encrypted {
+                    self.tls.get_mut().incoming.extend_from_slice(&message.payload);
+                    let mut payload = vec![0u8; 0x10000];
+                    let size = self.tls.ssl_read(payload.as_mut_slice()).unwrap();
+                    payload.truncate(size);
+                    message.payload = payload;
+                }
+                if message.channel_id != ChannelID::Control || !matches!(message.typed_message_id(), Ok(ControlMessageID::PING_REQUEST)) {
+                    return message;
+                }
+            }
+        }
+
+        ///Send an encrypted message to the head unit
+        fn send<I: crate::messenger::MessageID, M: protobuf::Message>(&mut self, channel_id: ChannelID, message_type: MessageType, message_id: I, body: &M) {
+            use crate::transport::Transport;
+            let mut payload = message_id.word().to_be_bytes().to_vec();
+            payload.extend(body.write_to_bytes().unwrap());
+            self.tls.ssl_write(&payload).unwrap();
+            let message = Message {
+                frame_header: FrameHeader { encryption_type: EncryptionType::Encrypted, message_type, frame_type: FrameType::Bulk },
+                channel_id,
+                payload: std::mem::take(&mut self.tls.get_mut().outgoing),
+            };
+            self.transport.send_buffer(message.to_byte_vector().as_slice(), std::time::Duration::ZERO).unwrap();
+        }
+
+        ///Ask `entity` for its services, so that the channel ids get assigned
+        fn discover(&mut self, entity: &mut crate::androidautoentity::AndroidAutoEntity<crate::transport::loopback::LoopbackTransport>) -> crate::protos::ServiceDiscoveryResponseMessage::ServiceDiscoveryResponse {
+            use crate::messenger::ControlMessageID;
+            use crate::protos::ServiceDiscoveryRequestMessage::ServiceDiscoveryRequest;
+            use crate::protos::ServiceDiscoveryResponseMessage::ServiceDiscoveryResponse;
+
+            let mut request = ServiceDiscoveryRequest::new();
+            request.set_device_name("Test Phone".to_string());
+            request.set_device_brand("Test".to_string());
+            self.send(ChannelID::Control, MessageType::Specific, ControlMessageID::SERVICE_DISCOVERY_REQUEST, &request);
+            entity.poll().unwrap();
+            let (message_id, response) = self.receive().decode::<ControlMessageID, ServiceDiscoveryResponse>().unwrap();
+            assert_eq!(message_id, ControlMessageID::SERVICE_DISCOVERY_RESPONSE);
+            response
+        }
+    }
+
+    ///Service that only records whether the session stopped it
+    struct StoppableService {
+        stopped: std::rc::Rc<std::cell::Cell<bool>>,
+    }
+
+    impl crate::services::Service for StoppableService {
+        fn start(&self) {}
+        fn stop(&self) {
+            self.stopped.set(true);
+        }
+        fn pause(&self) {}
+        fn resume(&self) {}
+        fn fill_features(&self, _channel_id: ChannelID, _response: &mut crate::protos::ServiceDiscoveryResponseMessage::ServiceDiscoveryResponse) {}
+    }
+
+    ///Channel handler that only records whether the session closed it
+    struct ClosableChannel {
+        closed: std::rc::Rc<std::cell::Cell<bool>>,
+    }
+
+    impl crate::channels::ChannelHandler for ClosableChannel {
+        fn handle_message(&mut self, _message: &Message, _context: &crate::channels::ChannelContext) -> Result<(), crate::error::ProtocolError> {
+            Ok(())
+        }
+
+        fn close(&mut self) {
+            self.closed.set(true);
+        }
+    }
+
+    #[test]
+    fn test_tls_handshake_over_loopback() {
+        use std::sync::mpsc::channel;
+        use std::time::Duration;
+        use openssl::ssl::{ErrorCode, Ssl, SslContext, SslMethod, SslStream};
+        use crate::cryptor::Cryptor;
+        use crate::messenger::Messenger;
+        use crate::transport::Transport;
+        use crate::transport::loopback::LoopbackTransport;
+
+        let (certificate, private_key) = test_identity();
+        let mut context = SslContext::builder(SslMethod::tls_server()).unwrap();
+        context.set_certificate(&certificate).unwrap();
+        context.set_private_key(&private_key).unwrap();
+        let mut phone_ssl = Ssl::new(&context.build()).unwrap();
+        phone_ssl.set_accept_state();
+        let mut phone_tls = SslStream::new(phone_ssl, PhoneBio::default()).unwrap();
+
+        let (head_unit, phone) = LoopbackTransport::pair();
+        let mut messenger = Messenger::init(head_unit);
+        messenger.set_cryptor(Cryptor::new(&certificate, &private_key).unwrap());
+        let (in_tx, in_rx) = channel();
+        let (out_tx, out_rx) = channel();
+        let mut channels = crate::channels::ChannelRegistry::new(out_tx);
+        channels.register(ChannelID::Control, Box::new(crate::channels::control_service_channel::ControlServiceChannel));
+        messenger.start_handshake().unwrap();
+
+        let mut buffer = vec![0u8; 0x10000];
+        let mut auth_complete = false;
+        while !auth_complete {
+            let size = phone.read_buffer(buffer.as_mut_slice(), Duration::from_millis(500)).unwrap();
+            let message = Message::from_data_frame(&buffer[..size]).unwrap();
+            assert_eq!(message.channel_id, ChannelID::Control);
+            match message.message_id().unwrap() {
+                3 => {
+                    phone_tls.get_mut().incoming.extend_from_slice(&message.payload[2..]);
+                    if let Err(e) = phone_tls.do_handshake() {
+                        assert_eq!(e.code(), ErrorCode::WANT_READ);
+                    }
+                    let mut payload = vec![0, 3];
+                    payload.append(&mut phone_tls.get_mut().outgoing);
+                    if payload.len() > 2 {
+                        let reply = Message { frame_header: message.frame_header, channel_id: ChannelID::Control, payload };
+                        phone.send_buffer(reply.to_byte_vector().as_slice(), Duration::ZERO).unwrap();
+                        in_tx.send(0).unwrap();
+                        messenger.run(&in_rx, &out_rx, &mut channels);
+                    }
+                }
+                4 => auth_complete = true,
+                id => panic!("unexpected control message {id}"),
+            }
+        }
+        assert!(messenger.is_authenticated());
+        let presented = messenger.cryptor().unwrap().presented_certificate().unwrap();
+        assert_eq!(presented.to_der().unwrap(), certificate.to_der().unwrap());
+
+        // head unit -> phone
+        let ping = Message {
+            frame_header: FrameHeader { encryption_type: EncryptionType::Encrypted, message_type: MessageType::Specific, frame_type: FrameType::Bulk },
+            channel_id: ChannelID::Control,
+            payload: vec![0, 0xb, 8, 1],
+        };
+        messenger.send_message(ping.clone()).unwrap();
+        let size = phone.read_buffer(buffer.as_mut_slice(), Duration::from_millis(500)).unwrap();
+        let encrypted = Message::from_data_frame(&buffer[..size]).unwrap();
+        assert_ne!(encrypted.payload, ping.payload);
+        phone_tls.get_mut().incoming.extend_from_slice(&encrypted.payload);
+        let size = phone_tls.ssl_read(buffer.as_mut_slice()).unwrap();
+        assert_eq!(&buffer[..size], ping.payload.as_slice());
+
+        // phone -> head unit
+        phone_tls.ssl_write(&[0, 0xc, 8, 1]).unwrap();
+        let reply = Message { frame_header: ping.frame_header, channel_id: ChannelID::Control, payload: std::mem::take(&mut phone_tls.get_mut().outgoing) };
+        let received = messenger.receive_bytes(reply.to_byte_vector().as_slice());
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].payload, vec![0, 0xc, 8, 1]);
+    }
+
+    #[test]
+    fn test_head_unit_identity() {
+        use crate::cryptor::{HeadUnitIdentity, IdentityError};
+
+        let (certificate, private_key) = test_identity();
+        let certificate_pem = certificate.to_pem().unwrap();
+        let private_key_pem = private_key.private_key_to_pem_pkcs8().unwrap();
+        let identity = HeadUnitIdentity::from_pem(&certificate_pem, &private_key_pem).unwrap();
+        assert_eq!(identity.certificate().to_der().unwrap(), certificate.to_der().unwrap());
+
+        let (_, other_key) = test_identity();
+        let other_key_pem = other_key.private_key_to_pem_pkcs8().unwrap();
+        assert!(matches!(HeadUnitIdentity::from_pem(&certificate_pem, &other_key_pem), Err(IdentityError::KeyMismatch)));
+        assert!(matches!(HeadUnitIdentity::from_pem(b"garbage", &private_key_pem), Err(IdentityError::InvalidCertificate(_))));
+        assert!(matches!(HeadUnitIdentity::from_pem_files("/nonexistent.crt", "/nonexistent.key"), Err(IdentityError::Io { .. })));
+    }
+
+    #[test]
+    fn test_version_negotiation() {
+        use std::time::Duration;
+        use crate::androidautoentity::{AndroidAutoEntity, AndroidAutoEvent, DisconnectReason};
+        use crate::channels::control_service_channel::ProtocolVersion;
+        use crate::cryptor::HeadUnitIdentity;
+        use crate::error::EntityError;
+        use crate::transport::{Transport, TransportError};
+        use crate::transport::loopback::LoopbackTransport;
+
+        let (certificate, private_key) = test_identity();
+        let identity = HeadUnitIdentity::new(certificate, private_key).unwrap();
+        let mut buffer = vec![0u8; 0x1000];
+
+        let (head_unit, phone) = LoopbackTransport::pair();
+        let mut entity = AndroidAutoEntity::new(head_unit);
+        entity.set_identity(identity.clone());
+        let events = entity.subscribe();
+        entity.start_session().unwrap();
+        let size = phone.read_buffer(buffer.as_mut_slice(), Duration::from_millis(500)).unwrap();
+        assert_eq!(&buffer[..size], &[0, 3, 0, 6, 0, 1, 0, 1, 0, 1]);
+        phone.send_buffer(&[0, 3, 0, 8, 0, 2, 0, 1, 0, 7, 0, 0], Duration::ZERO).unwrap();
+        entity.poll().unwrap();
+        assert_eq!(entity.negotiated_version(), Some(ProtocolVersion { major: 1, minor: 7 }));
+        assert_eq!(events.try_recv().unwrap(), AndroidAutoEvent::VersionNegotiated(ProtocolVersion { major: 1, minor: 7 }));
+        // the TLS handshake starts right after a matching version response
+        let size = phone.read_buffer(buffer.as_mut_slice(), Duration::from_millis(500)).unwrap();
+        assert_eq!(Message::from_data_frame(&buffer[..size]).unwrap().message_id().unwrap(), 3);
+
+        //a mismatch ends the session and releases the transport
+        let (head_unit, phone) = LoopbackTransport::pair();
+        let mut entity = AndroidAutoEntity::new(head_unit);
+        entity.set_identity(identity);
+        let events = entity.subscribe();
+        entity.start_session().unwrap();
+        phone.read_buffer(buffer.as_mut_slice(), Duration::from_millis(500)).unwrap();
+        phone.send_buffer(&[0, 3, 0, 8, 0, 2, 0, 2, 0, 0, 0xff, 0xff], Duration::ZERO).unwrap();
+        assert!(matches!(entity.poll(), Err(EntityError::VersionMismatch { phone: ProtocolVersion { major: 2, minor: 0 }, .. })));
+        assert_eq!(entity.negotiated_version(), None);
+        assert!(!entity.is_session_active());
+        assert_eq!(events.try_recv().unwrap(), AndroidAutoEvent::Disconnected(DisconnectReason::VersionMismatch));
+        assert!(matches!(phone.read_buffer(buffer.as_mut_slice(), Duration::ZERO), Err(TransportError::Disconnected)));
+    }
+
+    #[test]
+    fn test_requests_before_authentication() {
+        use std::time::Duration;
+        use crate::androidautoentity::{AndroidAutoEntity, AndroidAutoEvent};
+        use crate::cryptor::HeadUnitIdentity;
+        use crate::messenger::ControlMessageID;
+        use crate::protos::AudioFocusRequestMessage::AudioFocusRequest;
+        use crate::protos::AudioFocusTypeEnum::audio_focus_type;
+        use crate::protos::PingRequestMessage::PingRequest;
+        use crate::protos::ServiceDiscoveryRequestMessage::ServiceDiscoveryRequest;
+        use crate::transport::{Transport, TransportError};
+        use crate::transport::loopback::LoopbackTransport;
+
+        let (certificate, private_key) = test_identity();
+        let (head_unit, phone) = LoopbackTransport::pair();
+        let mut entity = AndroidAutoEntity::new(head_unit);
+        entity.set_identity(HeadUnitIdentity::new(certificate, private_key).unwrap());
+        let events = entity.subscribe();
+        entity.start_session().unwrap();
+        let mut buffer = vec![0u8; 0x1000];
+        phone.read_buffer(buffer.as_mut_slice(), Duration::from_millis(500)).unwrap();
+
+        //the answers would have to be encrypted, the requests are dropped and the session goes on
+        let mut service_discovery_request = ServiceDiscoveryRequest::new();
+        service_discovery_request.set_device_name("Test Phone".to_string());
+        service_discovery_request.set_device_brand("Test".to_string());
+        let mut ping_request = PingRequest::new();
+        ping_request.set_timestamp(1);
+        let mut audio_focus_request = AudioFocusRequest::new();
+        audio_focus_request.set_audio_focus_type(audio_focus_type::Enum::GAIN);
+        let mut requests = Message::from_proto(ChannelID::Control, EncryptionType::Plain, MessageType::Specific, ControlMessageID::SERVICE_DISCOVERY_REQUEST, &service_discovery_request).unwrap().to_byte_vector();
+        requests.extend(Message::from_proto(ChannelID::Control, EncryptionType::Plain, MessageType::Specific, ControlMessageID::PING_REQUEST, &ping_request).unwrap().to_byte_vector());
+        requests.extend(Message::from_proto(ChannelID::Control, EncryptionType::Plain, MessageType::Specific, ControlMessageID::AUDIO_FOCUS_REQUEST, &audio_focus_request).unwrap().to_byte_vector());
+        phone.send_buffer(requests.as_slice(), Duration::ZERO).unwrap();
+        entity.poll().unwrap();
+        assert!(entity.is_session_active());
+        assert!(matches!(phone.read_buffer(buffer.as_mut_slice(), Duration::ZERO), Err(TransportError::Timeout)));
+        assert!(events.try_recv().is_err());
+
+        phone.send_buffer(&[0, 3, 0, 8, 0, 2, 0, 1, 0, 7, 0, 0], Duration::ZERO).unwrap();
+        entity.poll().unwrap();
+        assert!(matches!(events.try_recv().unwrap(), AndroidAutoEvent::VersionNegotiated(_)));
+    }
+
+    #[test]
+    fn test_service_discovery_response() {
+        use std::time::Duration;
+        use protobuf::Message as protomsg;
+        use crate::androidautoentity::{AndroidAutoEntity, DriverPosition, HeadUnitConfig};
+        use crate::channels::control_service_channel::create_service_discovery_response_message;
+        use crate::cryptor::HeadUnitIdentity;
+        use crate::error::ProtocolError;
+        use crate::messenger::ControlMessageID;
+        use crate::protos::ServiceDiscoveryRequestMessage::ServiceDiscoveryRequest;
+        use crate::protos::VideoConfigData::VideoConfig;
+        use crate::services::sensor_service::SensorService;
+        use crate::services::video_service::VideoService;
+        use crate::transport::Transport;
+        use crate::transport::loopback::LoopbackTransport;
+
+        let (head_unit, _phone) = LoopbackTransport::pair();
+        let mut entity = AndroidAutoEntity::new(head_unit);
+        entity.set_config(HeadUnitConfig {
+            car_model: "Model T".to_string(),
+            driver_position: DriverPosition::Right,
+            ..HeadUnitConfig::default()
+        });
+        entity.add_service(Box::new(VideoService::default()));
+        entity.add_service(Box::new(SensorService {}));
+        let response = entity.create_service_discovery_response();
+        assert_eq!(response.car_model(), "Model T");
+        assert!(!response.left_hand_drive_vehicle());
+        let channel_ids: Vec<u32> = response.channels.iter().map(|channel| channel.channel_id()).collect();
+        assert_eq!(channel_ids, vec![1, 2]);
+        assert_eq!(entity.channel_ids(), vec![Some(ChannelID(1)), Some(ChannelID(2))]);
+        assert!(response.is_initialized());
+
+        //a video config without its required fields cannot be encoded, only the answer is dropped
+        let (certificate, private_key) = test_identity();
+        let (head_unit, phone) = LoopbackTransport::pair();
+        let mut entity = AndroidAutoEntity::new(head_unit);
+        entity.set_identity(HeadUnitIdentity::new(certificate, private_key).unwrap());
+        entity.add_service(Box::new(VideoService::new(VideoConfig::new())));
+        assert!(matches!(create_service_discovery_response_message(entity.create_service_discovery_response()), Err(ProtocolError::Encode(_))));
+        let mut phone = TestPhone::connect(&mut entity, phone);
+        let mut request = ServiceDiscoveryRequest::new();
+        request.set_device_name("Test Phone".to_string());
+        request.set_device_brand("Test".to_string());
+        phone.send(ChannelID::Control, MessageType::Specific, ControlMessageID::SERVICE_DISCOVERY_REQUEST, &request);
+        entity.poll().unwrap();
+        assert!(entity.is_session_active());
+        let mut buffer = vec![0u8; 0x1000];
+        while let Ok(size) = phone.transport.read_buffer(buffer.as_mut_slice(), Duration::from_millis(100)) {
+            let mut message = Message::from_data_frame(&buffer[..size]).unwrap();
+            phone.tls.get_mut().incoming.extend_from_slice(&message.payload);
+            let mut payload = vec![0u8; 0x1000];
+            message.payload.truncate(phone.tls.ssl_read(payload.as_mut_slice()).unwrap());
+            assert_ne!(message.typed_message_id::<ControlMessageID>().ok(), Some(ControlMessageID::SERVICE_DISCOVERY_RESPONSE));
+        }
+    }
+
+    #[test]
+    fn test_channel_registry() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        use std::sync::mpsc::channel;
+        use protobuf::Message as protomsg;
+        use crate::channels::{ChannelContext, ChannelHandler, ChannelRegistry};
+        use crate::error::ProtocolError;
+        use crate::protos::ChannelOpenRequestMessage::ChannelOpenRequest;
+        use crate::protos::ChannelOpenResponseMessage::ChannelOpenResponse;
+        use crate::protos::StatusEnum::status;
+
+        struct RecordingChannel {
+            accept: bool,
+            received: Rc<RefCell<Vec<Vec<u8>>>>,
+        }
+
+        impl ChannelHandler for RecordingChannel {
+            fn open(&mut self, _request: &ChannelOpenRequest, _context: &ChannelContext) -> bool {
+                self.accept
+            }
+
+            fn handle_message(&mut self, message: &Message, _context: &ChannelContext) -> Result<(), ProtocolError> {
+                self.received.borrow_mut().push(message.payload.clone());
+                Ok(())
+            }
+        }
+
+        let (sender, receiver) = channel();
+        let mut channels = ChannelRegistry::new(sender);
+        assert!(channels.register(ChannelID::Video, Box::new(crate::channels::video_service_channel::VideoServiceChannel::default())).is_none());
+        let received = Rc::new(RefCell::new(Vec::new()));
+        assert!(channels.register(ChannelID::Video, Box::new(RecordingChannel { accept: false, received: received.clone() })).is_some());
+
+        let mut open_request = ChannelOpenRequest::new();
+        open_request.set_priority(0);
+        open_request.set_channel_id(ChannelID::Video.0 as i32);
+        let mut payload = vec![0, 7];
+        payload.extend(open_request.write_to_bytes().unwrap());
+        let open_message = Message {
+            frame_header: FrameHeader { encryption_type: EncryptionType::Encrypted, message_type: MessageType::Control, frame_type: FrameType::Bulk },
+            channel_id: ChannelID::Video,
+            payload,
+        };
+        channels.dispatch(&open_message).unwrap();
+        let response = receiver.try_recv().unwrap();
+        assert_eq!(response.channel_id, ChannelID::Video);
+        assert_eq!(response.message_id().unwrap(), 8);
+        assert_eq!(ChannelOpenResponse::parse_from_bytes(&response.payload[2..]).unwrap().status(), status::Enum::FAIL);
+
+        let media_message = Message {
+            frame_header: FrameHeader { encryption_type: EncryptionType::Encrypted, message_type: MessageType::Specific, frame_type: FrameType::Bulk },
+            channel_id: ChannelID::Video,
+            payload: vec![0, 1, 9, 9],
+        };
+        channels.dispatch(&media_message).unwrap();
+        assert_eq!(*received.borrow(), vec![vec![0, 1, 9, 9]]);
+
+        assert!(channels.remove(ChannelID::Video).is_some());
+        assert!(matches!(channels.dispatch(&media_message), Err(ProtocolError::UnknownChannel(3))));
+        assert_eq!(received.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_channel_assignment() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+        use std::sync::mpsc::channel;
+        use crate::channels::ChannelRegistry;
+        use crate::channels::sensor_service_channel::SensorServiceChannel;
+        use crate::error::ProtocolError;
+        use crate::protos::ServiceDiscoveryResponseMessage::ServiceDiscoveryResponse;
+        use crate::services::ServiceRegistry;
+        use crate::protos::AudioTypeEnum::audio_type;
+        use crate::services::audio_service::AudioService;
+        use crate::services::wifi_service::WifiService;
+
+        let (sender, _receiver) = channel();
+        let mut channels = ChannelRegistry::new(sender);
+        channels.register(ChannelID(1), Box::new(SensorServiceChannel));
+        let mut services = ServiceRegistry::new();
+        services.add(Box::new(AudioService::with_default_config(audio_type::Enum::MEDIA)));
+        services.add(Box::new(AudioService::with_default_config(audio_type::Enum::MEDIA)));
+        services.add(Box::new(WifiService {}));
+
+        let mut response = ServiceDiscoveryResponse::new();
+        services.assign_channels(&mut response, &mut channels);
+        let channel_ids: Vec<u32> = response.channels.iter().map(|channel| channel.channel_id()).collect();
+        assert_eq!(channel_ids, vec![2, 3, 4]);
+        assert_eq!(services.channel_ids(), vec![Some(ChannelID(2)), Some(ChannelID(3)), Some(ChannelID(4))]);
+        assert!(channels.contains(ChannelID(1)));
+        assert!(channels.contains(ChannelID(2)) && channels.contains(ChannelID(3)));
+        assert!(!channels.contains(ChannelID(4)));
+
+        let message = Message {
+            frame_header: FrameHeader { encryption_type: EncryptionType::Encrypted, message_type: MessageType::Specific, frame_type: FrameType::Bulk },
+            channel_id: ChannelID::from(200),
+            payload: vec![0, 1],
+        };
+        assert!(matches!(channels.dispatch(&message), Err(ProtocolError::UnknownChannel(200))));
+
+        //a new session assigns the same ids again instead of leaking the old ones
+        let mut response = ServiceDiscoveryResponse::new();
+        services.assign_channels(&mut response, &mut channels);
+        assert_eq!(services.channel_ids(), vec![Some(ChannelID(2)), Some(ChannelID(3)), Some(ChannelID(4))]);
+
+        //a handler the application replaced a service's one with survives the next discovery
+        let closed = Rc::new(Cell::new(false));
+        channels.register(ChannelID(2), Box::new(ClosableChannel { closed: closed.clone() }));
+        let mut response = ServiceDiscoveryResponse::new();
+        services.assign_channels(&mut response, &mut channels);
+        assert_eq!(services.channel_ids(), vec![Some(ChannelID(2)), Some(ChannelID(3)), Some(ChannelID(4))]);
+        assert_eq!(response.channels[0].channel_id(), 2);
+        assert!(channels.contains(ChannelID(2)));
+        assert!(!closed.get());
+        channels.close_all();
+        assert!(closed.get());
+    }
+
+    #[test]
+    fn test_typed_message_ids() {
+        use crate::error::ProtocolError;
+        use crate::messenger::{AVMessageID, ControlMessageID, InputMessageID, MessageID, SensorMessageID};
+        use crate::protos::ChannelOpenRequestMessage::ChannelOpenRequest;
+
+        assert_eq!(AVMessageID::from_word(0x8008).unwrap(), AVMessageID::VIDEO_FOCUS_INDICATION);
+        assert_eq!(ControlMessageID::AUDIO_FOCUS_RESPONSE.word(), 0x0013);
+        assert_eq!(InputMessageID::BINDING_RESPONSE.word(), 0x8003);
+        assert!(matches!(SensorMessageID::from_word(0x8004), Err(ProtocolError::UnknownMessageId(0x8004))));
+
+        let mut request = ChannelOpenRequest::new();
+        request.set_priority(1);
+        request.set_channel_id(5);
+        let message = Message::from_proto(ChannelID::from(5), EncryptionType::Encrypted, MessageType::Control, ControlMessageID::CHANNEL_OPEN_REQUEST, &request).unwrap();
+        assert_eq!(&message.payload[..2], &[0, 7]);
+        let (message_id, decoded) = message.decode::<ControlMessageID, ChannelOpenRequest>().unwrap();
+        assert_eq!(message_id, ControlMessageID::CHANNEL_OPEN_REQUEST);
+        assert_eq!(decoded, request);
+        assert!(matches!(message.typed_message_id::<AVMessageID>(), Err(ProtocolError::UnknownMessageId(7))));
+    }
+
+    #[test]
+    fn test_pinger() {
+        use std::time::{Duration, Instant};
+        use crate::channels::control_service_channel::create_ping_request_message;
+        use crate::messenger::ControlMessageID;
+        use crate::pinger::{PingAction, Pinger};
+        use crate::protos::PingRequestMessage::PingRequest;
+
+        let start = Instant::now();
+        let mut pinger = Pinger::new(Duration::from_secs(1), Duration::from_secs(3));
+        assert_eq!(pinger.poll(start), PingAction::Idle);
+        pinger.start(start);
+        let first = match pinger.poll(start) {
+            PingAction::Send(timestamp) => timestamp,
+            action => panic!("expected a ping, got {:?}", action),
+        };
+        assert_eq!(pinger.poll(start + Duration::from_millis(500)), PingAction::Idle);
+        let second = match pinger.poll(start + Duration::from_secs(1)) {
+            PingAction::Send(timestamp) => timestamp,
+            action => panic!("expected a ping, got {:?}", action),
+        };
+        assert!(second > first);
+        assert_eq!(pinger.handle_response(second, start + Duration::from_millis(1200)), Some(Duration::from_millis(200)));
+        assert_eq!(pinger.round_trip_time(), Some(Duration::from_millis(200)));
+        //the first ping was implicitly answered by the second response
+        assert_eq!(pinger.handle_response(first, start + Duration::from_millis(1300)), None);
+
+        assert!(matches!(pinger.poll(start + Duration::from_secs(4)), PingAction::Send(_)));
+        assert_eq!(pinger.poll(start + Duration::from_millis(4300)), PingAction::TimedOut);
+
+        let message = create_ping_request_message(first).unwrap();
+        assert_eq!(message.channel_id, ChannelID::Control);
+        assert_eq!(message.frame_header.encryption_type, EncryptionType::Encrypted);
+        let (message_id, ping_request) = message.decode::<ControlMessageID, PingRequest>().unwrap();
+        assert_eq!(message_id, ControlMessageID::PING_REQUEST);
+        assert_eq!(ping_request.timestamp(), first);
+    }
+
+    #[test]
+    fn test_transport_error() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+        use std::time::Duration;
+        use crate::androidautoentity::{AndroidAutoEntity, AndroidAutoEvent, DisconnectReason};
+        use crate::cryptor::HeadUnitIdentity;
+        use crate::error::{EntityError, MessengerError};
+        use crate::transport::{Transport, TransportError};
+        use crate::transport::loopback::LoopbackTransport;
+
+        let (certificate, private_key) = test_identity();
+        let (head_unit, phone) = LoopbackTransport::pair();
+        let mut entity = AndroidAutoEntity::new(head_unit);
+        entity.set_identity(HeadUnitIdentity::new(certificate, private_key).unwrap());
+        let stopped = Rc::new(Cell::new(false));
+        entity.add_service(Box::new(StoppableService { stopped: stopped.clone() }));
+        let closed = Rc::new(Cell::new(false));
+        entity.register_channel(ChannelID::from(9), Box::new(ClosableChannel { closed: closed.clone() }));
+        let events = entity.subscribe();
+        entity.start_session().unwrap();
+        assert!(entity.is_session_active());
+
+        //the cable is pulled, long before a ping could time out
+        drop(phone);
+        assert!(matches!(entity.poll(), Err(EntityError::Messenger(MessengerError::Transport(TransportError::Disconnected)))));
+        assert!(!entity.is_session_active());
+        assert!(stopped.get());
+        assert!(closed.get());
+        assert_eq!(events.try_recv().unwrap(), AndroidAutoEvent::Disconnected(DisconnectReason::TransportError));
+        assert!(events.try_recv().is_err());
+        entity.poll().unwrap();
+
+        //a broken TLS handshake ends the session the same way
+        let (certificate, private_key) = test_identity();
+        let (head_unit, phone) = LoopbackTransport::pair();
+        let mut entity = AndroidAutoEntity::new(head_unit);
+        entity.set_identity(HeadUnitIdentity::new(certificate, private_key).unwrap());
+        let stopped = Rc::new(Cell::new(false));
+        entity.add_service(Box::new(StoppableService { stopped: stopped.clone() }));
+        let closed = Rc::new(Cell::new(false));
+        entity.register_channel(ChannelID::from(9), Box::new(ClosableChannel { closed: closed.clone() }));
+        let events = entity.subscribe();
+        entity.start_session().unwrap();
+        phone.send_buffer(&[0, 3, 0, 8, 0, 2, 0, 1, 0, 7, 0, 0], Duration::ZERO).unwrap();
+        entity.poll().unwrap();
+        assert!(matches!(events.try_recv().unwrap(), AndroidAutoEvent::VersionNegotiated(_)));
+        //a record that is not TLS at all
+        phone.send_buffer(b"\0\x03\0\x0a\0\x03garbage!", Duration::ZERO).unwrap();
+        assert!(matches!(entity.poll(), Err(EntityError::Messenger(MessengerError::Cryptor(_)))));
+        assert!(!entity.is_session_active());
+        assert!(stopped.get());
+        assert!(closed.get());
+        assert_eq!(events.try_recv().unwrap(), AndroidAutoEvent::Disconnected(DisconnectReason::Error));
+        let mut buffer = vec![0u8; 0x1000];
+        while phone.read_buffer(buffer.as_mut_slice(), Duration::ZERO).is_ok() {}
+        assert!(matches!(phone.read_buffer(buffer.as_mut_slice(), Duration::ZERO), Err(TransportError::Disconnected)));
+    }
+
+    #[test]
+    fn test_shutdown() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+        use std::time::Duration;
+        use crate::androidautoentity::{AndroidAutoEntity, AndroidAutoEvent, DisconnectReason};
+        use crate::channels::control_service_channel::create_shutdown_request_message;
+        use crate::cryptor::HeadUnitIdentity;
+        use crate::messenger::ControlMessageID;
+        use crate::protos::ShutdownReasonEnum::shutdown_reason;
+        use crate::protos::ShutdownRequestMessage::ShutdownRequest;
+        use crate::protos::ShutdownResponseMessage::ShutdownResponse;
+        use crate::transport::{Transport, TransportError};
+        use crate::transport::loopback::LoopbackTransport;
+
+        let (certificate, private_key) = test_identity();
+        let (head_unit, _phone) = LoopbackTransport::pair();
+        let mut entity = AndroidAutoEntity::new(head_unit);
+        entity.set_identity(HeadUnitIdentity::new(certificate, private_key).unwrap());
+        let stopped = Rc::new(Cell::new(false));
+        entity.add_service(Box::new(StoppableService { stopped: stopped.clone() }));
+        let events = entity.subscribe();
+        entity.start_session().unwrap();
+        assert!(entity.is_session_active());
+
+        //before the TLS handshake the request cannot be sent, the session ends right away
+        entity.shutdown_handle().request_shutdown(shutdown_reason::Enum::QUIT);
+        entity.poll().unwrap();
+        assert!(!entity.is_session_active());
+        assert!(stopped.get());
+        assert_eq!(events.try_recv().unwrap(), AndroidAutoEvent::Disconnected(DisconnectReason::Shutdown));
+
+        let message = create_shutdown_request_message(shutdown_reason::Enum::QUIT).unwrap();
+        assert_eq!(message.frame_header.encryption_type, EncryptionType::Encrypted);
+        let (message_id, shutdown_request) = message.decode::<ControlMessageID, ShutdownRequest>().unwrap();
+        assert_eq!(message_id, ControlMessageID::SHUTDOWN_REQUEST);
+        assert_eq!(shutdown_request.reason(), shutdown_reason::Enum::QUIT);
+
+        //the phone asks for the shutdown, it is answered and the session ends right away
+        let (certificate, private_key) = test_identity();
+        let (head_unit, phone) = LoopbackTransport::pair();
+        let mut entity = AndroidAutoEntity::new(head_unit);
+        entity.set_identity(HeadUnitIdentity::new(certificate, private_key).unwrap());
+        let stopped = Rc::new(Cell::new(false));
+        entity.add_service(Box::new(StoppableService { stopped: stopped.clone() }));
+        let closed = Rc::new(Cell::new(false));
+        entity.register_channel(ChannelID::from(9), Box::new(ClosableChannel { closed: closed.clone() }));
+        let events = entity.subscribe();
+        let mut phone = TestPhone::connect(&mut entity, phone);
+        assert!(matches!(events.try_recv().unwrap(), AndroidAutoEvent::VersionNegotiated(_)));
+
+        let mut shutdown_request = ShutdownRequest::new();
+        shutdown_request.set_reason(shutdown_reason::Enum::QUIT);
+        phone.send(ChannelID::Control, MessageType::Specific, ControlMessageID::SHUTDOWN_REQUEST, &shutdown_request);
+        entity.poll().unwrap();
+        assert!(!entity.is_session_active());
+        assert!(stopped.get());
+        assert!(closed.get());
+        assert_eq!(events.try_recv().unwrap(), AndroidAutoEvent::Disconnected(DisconnectReason::PhoneShutdown(shutdown_reason::Enum::QUIT)));
+        assert!(events.try_recv().is_err());
+        let (message_id, _) = phone.receive().decode::<ControlMessageID, ShutdownResponse>().unwrap();
+        assert_eq!(message_id, ControlMessageID::SHUTDOWN_RESPONSE);
+        //the transport was released, the phone reads nothing after the response
+        let mut buffer = [0u8; 16];
+        assert!(matches!(phone.transport.read_buffer(&mut buffer, Duration::ZERO), Err(TransportError::Disconnected)));
+    }
+
+    #[test]
+    fn test_audio_focus() {
+        use crate::focus::AudioFocusManager;
+        use crate::protos::AudioFocusStateEnum::audio_focus_state::Enum as State;
+        use crate::protos::AudioFocusTypeEnum::audio_focus_type::Enum as Type;
+
+        let mut audio_focus = AudioFocusManager::new();
+        assert_eq!(audio_focus.handle_request(Type::GAIN), State::GAIN);
+        assert_eq!(audio_focus.handle_request(Type::GAIN_NAVI), State::GAIN_TRANSIENT_GUIDANCE_ONLY);
+        assert_eq!(audio_focus.handle_request(Type::GAIN), State::GAIN);
+
+        //a chime ducks the phone, navigation prompts may still play
+        assert_eq!(audio_focus.revoke(State::LOSS_TRANSIENT_CAN_DUCK), Some(State::LOSS_TRANSIENT_CAN_DUCK));
+        assert_eq!(audio_focus.revoke(State::LOSS_TRANSIENT_CAN_DUCK), None);
+        assert_eq!(audio_focus.handle_request(Type::GAIN_TRANSIENT), State::LOSS_TRANSIENT_CAN_DUCK);
+        assert_eq!(audio_focus.handle_request(Type::GAIN_NAVI), State::GAIN_TRANSIENT_GUIDANCE_ONLY);
+        assert_eq!(audio_focus.handle_request(Type::GAIN), State::LOSS_TRANSIENT_CAN_DUCK);
+        assert_eq!(audio_focus.restore(), Some(State::GAIN));
+        assert_eq!(audio_focus.restore(), None);
+
+        //the radio takes over and the phone gives up in the meantime
+        assert_eq!(audio_focus.revoke(State::LOSS), Some(State::LOSS));
+        assert_eq!(audio_focus.handle_request(Type::RELEASE), State::LOSS);
+        assert_eq!(audio_focus.restore(), None);
+        assert!(!audio_focus.is_revoked());
+        assert_eq!(audio_focus.phone_state(), State::LOSS);
+    }
+
+    #[test]
+    fn test_navigation_focus() {
+        use crate::channels::control_service_channel::create_navigation_focus_response_message;
+        use crate::focus::{NavigationFocus, NavigationFocusManager};
+        use crate::messenger::ControlMessageID;
+        use crate::protos::NavigationFocusResponseMessage::NavigationFocusResponse;
+
+        let mut navigation_focus = NavigationFocusManager::new();
+        assert_eq!(navigation_focus.handle_request(NavigationFocus::Projected), NavigationFocus::Projected);
+        assert_eq!(navigation_focus.take(), Some(NavigationFocus::Native));
+        assert_eq!(navigation_focus.handle_request(NavigationFocus::Projected), NavigationFocus::Native);
+        assert_eq!(navigation_focus.release(), Some(NavigationFocus::Projected));
+
+        assert_eq!(navigation_focus.set_allow_projected(false), Some(NavigationFocus::Native));
+        assert_eq!(navigation_focus.handle_request(NavigationFocus::Projected), NavigationFocus::Native);
+        assert_eq!(navigation_focus.set_allow_projected(true), Some(NavigationFocus::Projected));
+        assert_eq!(navigation_focus.handle_request(NavigationFocus::Native), NavigationFocus::Native);
+        assert_eq!(navigation_focus.release(), None);
+        assert!(NavigationFocus::try_from(3).is_err());
+
+        let message = create_navigation_focus_response_message(NavigationFocus::Projected).unwrap();
+        let (message_id, response) = message.decode::<ControlMessageID, NavigationFocusResponse>().unwrap();
+        assert_eq!(message_id, ControlMessageID::NAVIGATION_FOCUS_RESPONSE);
+        assert_eq!(response.type_(), 2);
+    }
+
+    #[test]
+    fn test_voice_session() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        use crate::androidautoentity::{AndroidAutoEntity, AndroidAutoEvent};
+        use crate::channels::{ChannelContext, ChannelHandler};
+        use crate::channels::audio_service_channel::{default_audio_config, AudioServiceChannel, DEFAULT_AUDIO_MAX_UNACKED};
+        use crate::channels::av_input_service_channel::AVInputServiceChannel;
+        use crate::cryptor::HeadUnitIdentity;
+        use crate::error::ProtocolError;
+        use crate::media::{AudioSink, AudioSource};
+        use crate::messenger::ControlMessageID;
+        use crate::protos::AudioTypeEnum::audio_type;
+        use crate::protos::VoiceSessionRequestMessage::VoiceSessionRequest;
+        use crate::transport::loopback::LoopbackTransport;
+
+        struct MicrophoneChannel {
+            voice_sessions: Rc<RefCell<Vec<bool>>>,
+        }
+
+        impl ChannelHandler for MicrophoneChannel {
+            fn handle_message(&mut self, _message: &Message, _context: &ChannelContext) -> Result<(), ProtocolError> {
+                Ok(())
+            }
+
+            fn voice_session_changed(&mut self, active: bool, _context: &ChannelContext) {
+                self.voice_sessions.borrow_mut().push(active);
+            }
+        }
+
+        //sink and source only record the voice sessions they are told about
+        #[derive(Default)]
+        struct VoiceSessions(Vec<bool>);
+
+        impl AudioSink for VoiceSessions {
+            fn write(&mut self, _timestamp: Option<u64>, _pcm: &[u8]) -> std::io::Result<()> {
+                Ok(())
+            }
+
+            fn voice_session_changed(&mut self, active: bool) {
+                self.0.push(active);
+            }
+        }
+
+        impl AudioSource for VoiceSessions {
+            fn read(&mut self, _buffer: &mut [u8]) -> std::io::Result<usize> {
+                Ok(0)
+            }
+
+            fn voice_session_changed(&mut self, active: bool) {
+                self.0.push(active);
+            }
+        }
+
+        let (certificate, private_key) = test_identity();
+        let (head_unit, phone) = LoopbackTransport::pair();
+        let mut entity = AndroidAutoEntity::new(head_unit);
+        entity.set_identity(HeadUnitIdentity::new(certificate, private_key).unwrap());
+        let voice_sessions = Rc::new(RefCell::new(Vec::new()));
+        entity.register_channel(ChannelID::from(9), Box::new(MicrophoneChannel { voice_sessions: voice_sessions.clone() }));
+        let speech_sink = Rc::new(RefCell::new(VoiceSessions::default()));
+        let mut speech_channel = AudioServiceChannel::new(audio_type::Enum::SPEECH, default_audio_config(audio_type::Enum::SPEECH), DEFAULT_AUDIO_MAX_UNACKED);
+        speech_channel.set_sink(speech_sink.clone());
+        entity.register_channel(ChannelID::SpeechAudio, Box::new(speech_channel));
+        let microphone_source = Rc::new(RefCell::new(VoiceSessions::default()));
+        let mut microphone_channel = AVInputServiceChannel::default();
+        microphone_channel.set_source(microphone_source.clone());
+        entity.register_channel(ChannelID::AVInput, Box::new(microphone_channel));
+        let events = entity.subscribe();
+        let mut phone = TestPhone::connect(&mut entity, phone);
+        assert!(matches!(events.try_recv().unwrap(), AndroidAutoEvent::VersionNegotiated(_)));
+
+        for voice_session_type in [1, 1, 2, 3] {
+            let mut request = VoiceSessionRequest::new();
+            request.type_ = voice_session_type;
+            phone.send(ChannelID::Control, MessageType::Specific, ControlMessageID::VOICE_SESSION_REQUEST, &request);
+            entity.poll().unwrap();
+        }
+        assert!(!entity.is_voice_session_active());
+        assert_eq!(events.try_recv().unwrap(), AndroidAutoEvent::VoiceSessionStarted);
+        assert_eq!(events.try_recv().unwrap(), AndroidAutoEvent::VoiceSessionStopped);
+        assert!(events.try_recv().is_err());
+        assert_eq!(*voice_sessions.borrow(), vec![true, false]);
+        assert_eq!(speech_sink.borrow().0, vec![true, false]);
+        assert_eq!(microphone_source.borrow().0, vec![true, false]);
+    }
+
+    #[test]
+    fn test_video_channel() {
+        use std::sync::mpsc::channel;
+        use crate::channels::ChannelRegistry;
+        use crate::messenger::AVMessageID;
+        use crate::protos::AVChannelSetupRequestMessage::AVChannelSetupRequest;
+        use crate::protos::AVChannelSetupResponseMessage::AVChannelSetupResponse;
+        use crate::protos::AVChannelSetupStatusEnum::avchannel_setup_status;
+        use crate::protos::AVChannelStartIndicationMessage::AVChannelStartIndication;
+        use crate::protos::AVChannelStopIndicationMessage::AVChannelStopIndication;
+        use crate::protos::AVMediaAckIndicationMessage::AVMediaAckIndication;
+        use crate::protos::VideoFocusIndicationMessage::VideoFocusIndication;
+        use crate::protos::VideoFocusModeEnum::video_focus_mode;
+        use crate::protos::VideoFocusReasonEnum::video_focus_reason;
+        use crate::protos::VideoFocusRequestMessage::VideoFocusRequest;
+        use crate::services::video_service::VideoService;
+        use crate::services::Service;
+        use protobuf::Message as _;
+
+        let video = ChannelID::Video;
+
+        let (sender, receiver) = channel();
+        let mut channels = ChannelRegistry::new(sender);
+        let mut service = VideoService::default();
+        service.set_max_unacked(2);
+        channels.register(video, service.channel_handler().unwrap());
+
+        let mut setup_request = AVChannelSetupRequest::new();
+        setup_request.set_config_index(0);
+        channels.dispatch(&phone_message(video, AVMessageID::SETUP_REQUEST, &setup_request.write_to_bytes().unwrap())).unwrap();
+        let (message_id, setup_response) = receiver.try_recv().unwrap().decode::<AVMessageID, AVChannelSetupResponse>().unwrap();
+        assert_eq!(message_id, AVMessageID::SETUP_RESPONSE);
+        assert_eq!(setup_response.media_status(), avchannel_setup_status::Enum::OK);
+        assert_eq!(setup_response.max_unacked(), 2);
+        let (message_id, focus_indication) = receiver.try_recv().unwrap().decode::<AVMessageID, VideoFocusIndication>().unwrap();
+        assert_eq!(message_id, AVMessageID::VIDEO_FOCUS_INDICATION);
+        assert_eq!(focus_indication.focus_mode(), video_focus_mode::Enum::FOCUSED);
+
+        //frames outside of a stream are not acknowledged
+        channels.dispatch(&phone_message(video, AVMessageID::AV_MEDIA_INDICATION, &[0, 0, 0, 1])).unwrap();
+        let mut start_indication = AVChannelStartIndication::new();
+        start_indication.set_session(7);
+        start_indication.set_config(0);
+        channels.dispatch(&phone_message(video, AVMessageID::START_INDICATION, &start_indication.write_to_bytes().unwrap())).unwrap();
+        for _ in 0..5 {
+            channels.dispatch(&phone_message(video, AVMessageID::AV_MEDIA_WITH_TIMESTAMP_INDICATION, &[0; 12])).unwrap();
+        }
+        for _ in 0..2 {
+            let (message_id, ack) = receiver.try_recv().unwrap().decode::<AVMessageID, AVMediaAckIndication>().unwrap();
+            assert_eq!(message_id, AVMessageID::AV_MEDIA_ACK_INDICATION);
+            assert_eq!((ack.session(), ack.value()), (7, 2));
+        }
+        assert!(receiver.try_recv().is_err());
+
+        let mut focus_request = VideoFocusRequest::new();
+        focus_request.set_focus_mode(video_focus_mode::Enum::UNFOCUSED);
+        focus_request.set_focus_reason(video_focus_reason::Enum::NONE);
+        channels.dispatch(&phone_message(video, AVMessageID::VIDEO_FOCUS_REQUEST, &focus_request.write_to_bytes().unwrap())).unwrap();
+        let (_, focus_indication) = receiver.try_recv().unwrap().decode::<AVMessageID, VideoFocusIndication>().unwrap();
+        assert_eq!(focus_indication.focus_mode(), video_focus_mode::Enum::UNFOCUSED);
+        assert!(!focus_indication.unrequested());
+
+        channels.dispatch(&phone_message(video, AVMessageID::STOP_INDICATION, &AVChannelStopIndication::new().write_to_bytes().unwrap())).unwrap();
+        channels.dispatch(&phone_message(video, AVMessageID::AV_MEDIA_WITH_TIMESTAMP_INDICATION, &[0; 12])).unwrap();
+        channels.dispatch(&phone_message(video, AVMessageID::AV_MEDIA_WITH_TIMESTAMP_INDICATION, &[0; 12])).unwrap();
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_video_sink() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        use std::sync::mpsc::channel;
+        use crate::channels::ChannelRegistry;
+        use crate::channels::video_service_channel::VideoServiceChannel;
+        use crate::media::H264FileSink;
+        use crate::messenger::AVMessageID;
+        use crate::protos::AVChannelStartIndicationMessage::AVChannelStartIndication;
+        use protobuf::Message as _;
+
+        let video = ChannelID::Video;
+
+        let sink = Rc::new(RefCell::new(H264FileSink::new(Vec::new())));
+        let mut video_channel = VideoServiceChannel::default();
+        video_channel.set_sink(sink.clone());
+        let (sender, _receiver) = channel();
+        let mut channels = ChannelRegistry::new(sender);
+        channels.register(video, Box::new(video_channel));
+
+        let mut start_indication = AVChannelStartIndication::new();
+        start_indication.set_session(1);
+        start_indication.set_config(0);
+        channels.dispatch(&phone_message(video, AVMessageID::START_INDICATION, &start_indication.write_to_bytes().unwrap())).unwrap();
+        //SPS and PPS without a timestamp, then an IDR slice in its own start code and one without
+        channels.dispatch(&phone_message(video, AVMessageID::AV_MEDIA_INDICATION, &[0, 0, 0, 1, 0x67, 0x42, 0, 0, 0, 1, 0x68, 0xce])).unwrap();
+        let mut frame = 1_000_000u64.to_be_bytes().to_vec();
+        frame.extend([0, 0, 0, 1, 0x65, 0x88]);
+        channels.dispatch(&phone_message(video, AVMessageID::AV_MEDIA_WITH_TIMESTAMP_INDICATION, &frame)).unwrap();
+        let mut frame = 1_033_333u64.to_be_bytes().to_vec();
+        frame.extend([0x41, 0x9a]);
+        channels.dispatch(&phone_message(video, AVMessageID::AV_MEDIA_WITH_TIMESTAMP_INDICATION, &frame)).unwrap();
+        assert!(matches!(
+            channels.dispatch(&phone_message(video, AVMessageID::AV_MEDIA_WITH_TIMESTAMP_INDICATION, &[0, 1, 2])),
+            Err(crate::error::ProtocolError::MissingTimestamp)
+        ));
+        channels.remove(video).unwrap();
+
+        let sink = Rc::try_unwrap(sink).ok().unwrap().into_inner();
+        assert_eq!(sink.into_inner(), vec![0, 0, 0, 1, 0x67, 0x42, 0, 0, 0, 1, 0x68, 0xce, 0, 0, 0, 1, 0x65, 0x88, 0, 0, 0, 1, 0x41, 0x9a]);
+    }
+
+    #[test]
+    fn test_audio_channels() {
+        use std::cell::RefCell;
+        use std::io::Cursor;
+        use std::rc::Rc;
+        use std::sync::mpsc::channel;
+        use crate::channels::ChannelRegistry;
+        use crate::channels::audio_service_channel::DEFAULT_AUDIO_MAX_UNACKED;
+        use crate::media::WavFileSink;
+        use crate::messenger::AVMessageID;
+        use crate::protos::AVChannelSetupRequestMessage::AVChannelSetupRequest;
+        use crate::protos::AVChannelSetupResponseMessage::AVChannelSetupResponse;
+        use crate::protos::AVChannelSetupStatusEnum::avchannel_setup_status;
+        use crate::protos::AVChannelStartIndicationMessage::AVChannelStartIndication;
+        use crate::protos::AVMediaAckIndicationMessage::AVMediaAckIndication;
+        use crate::protos::AudioTypeEnum::audio_type;
+        use crate::protos::ServiceDiscoveryResponseMessage::ServiceDiscoveryResponse;
+        use crate::services::Service;
+        use crate::services::audio_service::AudioService;
+        use protobuf::Message as _;
+
+        let sink = Rc::new(RefCell::new(WavFileSink::new(Cursor::new(Vec::new()))));
+        let mut media_audio_service = AudioService::with_default_config(audio_type::Enum::MEDIA);
+        media_audio_service.set_sink(sink.clone());
+        let mut speech_audio_service = AudioService::with_default_config(audio_type::Enum::SPEECH);
+        speech_audio_service.set_max_unacked(4);
+        let mut response = ServiceDiscoveryResponse::new();
+        media_audio_service.fill_features(ChannelID::MediaAudio, &mut response);
+        speech_audio_service.fill_features(ChannelID::SpeechAudio, &mut response);
+        assert_eq!(response.channels[0].av_channel.audio_type(), audio_type::Enum::MEDIA);
+        assert_eq!(response.channels[0].av_channel.audio_configs[0].sample_rate(), 48000);
+        assert_eq!(response.channels[1].av_channel.audio_type(), audio_type::Enum::SPEECH);
+        assert_eq!(response.channels[1].av_channel.audio_configs[0].channel_count(), 1);
+
+        let (sender, receiver) = channel();
+        let mut channels = ChannelRegistry::new(sender);
+        channels.register(ChannelID::MediaAudio, media_audio_service.channel_handler().unwrap());
+        channels.register(ChannelID::SpeechAudio, speech_audio_service.channel_handler().unwrap());
+
+        let mut setup_request = AVChannelSetupRequest::new();
+        setup_request.set_config_index(0);
+        for (channel_id, max_unacked) in [(ChannelID::MediaAudio, DEFAULT_AUDIO_MAX_UNACKED), (ChannelID::SpeechAudio, 4)] {
+            channels.dispatch(&phone_message(channel_id, AVMessageID::SETUP_REQUEST, &setup_request.write_to_bytes().unwrap())).unwrap();
+            let response = receiver.try_recv().unwrap();
+            assert_eq!(response.channel_id, channel_id);
+            let (_, setup_response) = response.decode::<AVMessageID, AVChannelSetupResponse>().unwrap();
+            assert_eq!(setup_response.media_status(), avchannel_setup_status::Enum::OK);
+            assert_eq!(setup_response.max_unacked(), max_unacked);
+        }
+
+        //two streams of one and two buffers end up in one file
+        for (session, buffers) in [(1, 1), (2, 2)] {
+            let mut start_indication = AVChannelStartIndication::new();
+            start_indication.set_session(session);
+            start_indication.set_config(0);
+            channels.dispatch(&phone_message(ChannelID::MediaAudio, AVMessageID::START_INDICATION, &start_indication.write_to_bytes().unwrap())).unwrap();
+            for _ in 0..buffers {
+                let mut media = 20_000u64.to_be_bytes().to_vec();
+                media.extend([1, 0, 2, 0, 3, 0, 4, 0]);
+                channels.dispatch(&phone_message(ChannelID::MediaAudio, AVMessageID::AV_MEDIA_WITH_TIMESTAMP_INDICATION, &media)).unwrap();
+                let (message_id, ack) = receiver.try_recv().unwrap().decode::<AVMessageID, AVMediaAckIndication>().unwrap();
+                assert_eq!(message_id, AVMessageID::AV_MEDIA_ACK_INDICATION);
+                assert_eq!((ack.session(), ack.value()), (session, 1));
+            }
+            channels.dispatch(&phone_message(ChannelID::MediaAudio, AVMessageID::STOP_INDICATION, &[])).unwrap();
+        }
+        assert!(receiver.try_recv().is_err());
+
+        assert_eq!(sink.borrow().data_size(), 24);
+        drop(channels);
+        drop(media_audio_service);
+        let wav = Rc::try_unwrap(sink).ok().unwrap().into_inner().into_inner().into_inner();
+        assert_eq!(wav.len(), 44 + 24);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 36 + 24);
+        assert_eq!(u16::from_le_bytes(wav[22..24].try_into().unwrap()), 2);
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 48000);
+        assert_eq!(u32::from_le_bytes(wav[28..32].try_into().unwrap()), 48000 * 4);
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 24);
+        assert_eq!(&wav[44..52], &[1, 0, 2, 0, 3, 0, 4, 0]);
+    }
+
+    #[test]
+    fn test_microphone() {
+        use std::cell::RefCell;
+        use std::io::Cursor;
+        use std::rc::Rc;
+        use std::sync::mpsc::channel;
+        use crate::channels::ChannelRegistry;
+        use crate::channels::av_input_service_channel::default_microphone_config;
+        use crate::media::{AudioSink, AudioSource, WavFileSink, WavFileSource};
+        use crate::messenger::AVMessageID;
+        use crate::protos::AVInputOpenRequestMessage::AVInputOpenRequest;
+        use crate::protos::AVInputOpenResponseMessage::AVInputOpenResponse;
+        use crate::protos::AVMediaAckIndicationMessage::AVMediaAckIndication;
+        use crate::protos::ServiceDiscoveryResponseMessage::ServiceDiscoveryResponse;
+        use crate::services::Service;
+        use crate::services::audio_input_service::AudioInputService;
+        use protobuf::Message as _;
+
+        let microphone = ChannelID::from(11);
+        let ack = || {
+            let mut ack = AVMediaAckIndication::new();
+            ack.set_session(0);
+            ack.set_value(1);
+            phone_message(microphone, AVMessageID::AV_MEDIA_ACK_INDICATION, &ack.write_to_bytes().unwrap())
+        };
+
+        //a recording of 1000 bytes makes one full 20 ms message of 640 bytes and a short one
+        let mut recording = WavFileSink::new(Cursor::new(Vec::new()));
+        recording.start(&default_microphone_config()).unwrap();
+        recording.write(None, &(0..1000).map(|i| i as u8).collect::<Vec<u8>>()).unwrap();
+        recording.stop().unwrap();
+        let mut recording = recording.into_inner();
+        recording.set_position(0);
+        let source = WavFileSource::new(recording).unwrap();
+        assert_eq!(*source.config(), default_microphone_config());
+
+        //chunk sizes from the header are not trusted, neither for allocation nor for skipping
+        for (chunk_id, chunk_size, error_kind) in [(b"fmt ", u32::MAX, std::io::ErrorKind::InvalidData), (b"LIST", u32::MAX, std::io::ErrorKind::UnexpectedEof)] {
+            let mut header = b"RIFF\0\0\0\0WAVE".to_vec();
+            header.extend(chunk_id);
+            header.extend(chunk_size.to_le_bytes());
+            assert_eq!(WavFileSource::new(Cursor::new(header)).err().unwrap().kind(), error_kind);
+        }
+
+        //an odd sized format chunk is followed by a pad byte ahead of the next chunk
+        let mut header = b"RIFF\0\0\0\0WAVEfmt \x11\0\0\0".to_vec();
+        header.extend(1u16.to_le_bytes());
+        header.extend(1u16.to_le_bytes());
+        header.extend(16000u32.to_le_bytes());
+        header.extend(32000u32.to_le_bytes());
+        header.extend(2u16.to_le_bytes());
+        header.extend(16u16.to_le_bytes());
+        header.extend(b"\0\0data\x02\0\0\0\x01\x02");
+        let mut odd_source = WavFileSource::new(Cursor::new(header)).unwrap();
+        assert_eq!(*odd_source.config(), default_microphone_config());
+        let mut samples = [0; 4];
+        assert_eq!(odd_source.read(&mut samples).unwrap(), 2);
+        assert_eq!(samples[..2], [1, 2]);
+
+        let mut audio_input_service = AudioInputService::new(source.config().clone());
+        audio_input_service.set_source(Rc::new(RefCell::new(source)));
+        let mut response = ServiceDiscoveryResponse::new();
+        audio_input_service.fill_features(microphone, &mut response);
+        assert_eq!(response.channels[0].av_input_channel.audio_config.sample_rate(), 16000);
+
+        let (sender, receiver) = channel();
+        let mut channels = ChannelRegistry::new(sender);
+        channels.register(microphone, audio_input_service.channel_handler().unwrap());
+        //nothing is sent before the phone opens the microphone
+        channels.poll_all();
+        assert!(receiver.try_recv().is_err());
+
+        let mut open_request = AVInputOpenRequest::new();
+        open_request.set_open(true);
+        open_request.set_max_unacked(1);
+        channels.dispatch(&phone_message(microphone, AVMessageID::AV_INPUT_OPEN_REQUEST, &open_request.write_to_bytes().unwrap())).unwrap();
+        let (message_id, _) = receiver.try_recv().unwrap().decode::<AVMessageID, AVInputOpenResponse>().unwrap();
+        assert_eq!(message_id, AVMessageID::AV_INPUT_OPEN_RESPONSE);
+
+        let mut samples: Vec<u8> = Vec::new();
+        for expected_size in [640, 360] {
+            channels.poll_all();
+            let media = receiver.try_recv().unwrap();
+            assert_eq!(media.channel_id, microphone);
+            assert_eq!(media.typed_message_id::<AVMessageID>().unwrap(), AVMessageID::AV_MEDIA_WITH_TIMESTAMP_INDICATION);
+            assert_eq!(media.body().len(), 8 + expected_size);
+            samples.extend(&media.body()[8..]);
+            //the window of one message is full until the phone acknowledges
+            channels.poll_all();
+            assert!(receiver.try_recv().is_err());
+            channels.dispatch(&ack()).unwrap();
+        }
+        assert_eq!(samples, (0..1000).map(|i| i as u8).collect::<Vec<u8>>());
+        channels.poll_all();
+        assert!(receiver.try_recv().is_err());
+
+        open_request.set_open(false);
+        channels.dispatch(&phone_message(microphone, AVMessageID::AV_INPUT_OPEN_REQUEST, &open_request.write_to_bytes().unwrap())).unwrap();
+        assert!(receiver.try_recv().unwrap().decode::<AVMessageID, AVInputOpenResponse>().is_ok());
+    }
+
+    #[test]
+    fn test_touch_events() {
+        use crate::androidautoentity::AndroidAutoEntity;
+        use crate::channels::input_service_channel::create_touch_event_indication_message;
+        use crate::cryptor::HeadUnitIdentity;
+        use crate::error::EntityError;
+        use crate::input::{TouchPoint, TouchScreen};
+        use crate::messenger::InputMessageID;
+        use crate::protos::InputEventIndicationMessage::InputEventIndication;
+        use crate::protos::TouchActionEnum::touch_action;
+        use crate::protos::TouchEventData::TouchEvent;
+        use crate::services::input_service::InputService;
+        use crate::transport::loopback::LoopbackTransport;
+
+        let locations = |touch_event: &TouchEvent| -> Vec<(u32, u32, u32)> {
+            touch_event.touch_location.iter().map(|location| (location.pointer_id(), location.x(), location.y())).collect()
+        };
+
+        let (head_unit, _phone) = LoopbackTransport::pair();
+        let mut entity = AndroidAutoEntity::new(head_unit);
+        entity.add_service(Box::new(InputService::default()));
+        assert!(matches!(entity.send_touch_down(TouchPoint::new(0, 10, 10)), Err(EntityError::NoInputChannel)));
+        let response = entity.create_service_discovery_response();
+        let touch_config = response.channels[0].input_channel.touch_screen_config.clone().unwrap();
+        assert_eq!((touch_config.width(), touch_config.height()), (800, 480));
+
+        //a 1600x960 panel driving the advertised 800x480 touch screen
+        let mut touch_screen = TouchScreen::new(1600, 960);
+        touch_screen.set_touch_config(Some(touch_config));
+        let press = touch_screen.down(TouchPoint::new(3, 200, 100));
+        assert_eq!(press.touch_action(), touch_action::Enum::PRESS);
+        assert_eq!(locations(&press), vec![(3, 100, 50)]);
+        let pointer_down = touch_screen.down(TouchPoint::new(5, 1600, 960));
+        assert_eq!(pointer_down.touch_action(), touch_action::Enum::POINTER_DOWN);
+        assert_eq!(pointer_down.action_index(), 1);
+        assert_eq!(locations(&pointer_down), vec![(3, 100, 50), (5, 799, 479)]);
+        let drag = touch_screen.move_pointers(&[TouchPoint::new(3, 400, 200), TouchPoint::new(9, 0, 0)]).unwrap();
+        assert_eq!(drag.touch_action(), touch_action::Enum::DRAG);
+        assert_eq!(locations(&drag), vec![(3, 200, 100), (5, 799, 479)]);
+        assert!(touch_screen.move_pointers(&[TouchPoint::new(9, 0, 0)]).is_none());
+        let pointer_up = touch_screen.up(3).unwrap();
+        assert_eq!(pointer_up.touch_action(), touch_action::Enum::POINTER_UP);
+        assert_eq!(pointer_up.action_index(), 0);
+        assert_eq!(locations(&pointer_up), vec![(3, 200, 100), (5, 799, 479)]);
+        let release = touch_screen.up(5).unwrap();
+        assert_eq!(release.touch_action(), touch_action::Enum::RELEASE);
+        assert_eq!(locations(&release), vec![(5, 799, 479)]);
+        assert!(touch_screen.up(5).is_none());
+        assert_eq!(touch_screen.pointer_count(), 0);
+
+        let message = create_touch_event_indication_message(ChannelID::from(2), release).unwrap();
+        let (message_id, indication) = message.decode::<InputMessageID, InputEventIndication>().unwrap();
+        assert_eq!(message_id, InputMessageID::INPUT_EVENT_INDICATION);
+        assert!(indication.timestamp() > 0);
+        assert_eq!(indication.touch_event.touch_action(), touch_action::Enum::RELEASE);
+
+        //after discovery the entity scales the panel coordinates and sends them on the input channel
+        let (certificate, private_key) = test_identity();
+        let (head_unit, phone) = LoopbackTransport::pair();
+        let mut entity = AndroidAutoEntity::new(head_unit);
+        entity.set_identity(HeadUnitIdentity::new(certificate, private_key).unwrap());
+        entity.add_service(Box::new(InputService::default()));
+        let mut phone = TestPhone::connect(&mut entity, phone);
+        let response = phone.discover(&mut entity);
+        let input_channel = ChannelID::from(response.channels[0].channel_id() as u8);
+        assert_eq!(entity.channel_ids(), vec![Some(input_channel)]);
+
+        //without a panel size the points are already in the advertised resolution
+        entity.send_touch_down(TouchPoint::new(3, 200, 100)).unwrap();
+        entity.send_touch_up(3).unwrap();
+        for action in [touch_action::Enum::PRESS, touch_action::Enum::RELEASE] {
+            let (_, indication) = phone.receive().decode::<InputMessageID, InputEventIndication>().unwrap();
+            assert_eq!(indication.touch_event.touch_action(), action);
+            assert_eq!(locations(&indication.touch_event), vec![(3, 200, 100)]);
+        }
+
+        entity.set_touch_panel_size(1600, 960);
+
+        entity.send_touch_down(TouchPoint::new(3, 200, 100)).unwrap();
+        entity.send_touch_move(&[TouchPoint::new(3, 1600, 960)]).unwrap();
+        entity.send_touch_up(3).unwrap();
+        for (action, location) in [(touch_action::Enum::PRESS, (3, 100, 50)), (touch_action::Enum::DRAG, (3, 799, 479)), (touch_action::Enum::RELEASE, (3, 799, 479))] {
+            let message = phone.receive();
+            assert_eq!(message.channel_id, input_channel);
+            let (message_id, indication) = message.decode::<InputMessageID, InputEventIndication>().unwrap();
+            assert_eq!(message_id, InputMessageID::INPUT_EVENT_INDICATION);
+            assert_eq!(indication.touch_event.touch_action(), action);
+            assert_eq!(locations(&indication.touch_event), vec![location]);
+        }
+    }
+
+    #[test]
+    fn test_buttons() {
+        use crate::androidautoentity::{AndroidAutoEntity, AndroidAutoEvent};
+        use crate::channels::input_service_channel::{create_binding_response_message, create_button_event_indication_message, create_relative_input_event_indication_message};
+        use crate::cryptor::HeadUnitIdentity;
+        use crate::error::EntityError;
+        use crate::input::{button_event, keycode, rotary_event, unsupported_keycodes, ButtonAction};
+        use crate::messenger::InputMessageID;
+        use crate::protos::BindingRequestMessage::BindingRequest;
+        use crate::protos::BindingResponseMessage::BindingResponse;
+        use crate::protos::ButtonCodeEnum::button_code;
+        use crate::protos::InputEventIndicationMessage::InputEventIndication;
+        use crate::protos::StatusEnum::status;
+        use crate::services::input_service::InputService;
+        use crate::transport::loopback::LoopbackTransport;
+
+        let (head_unit, _phone) = LoopbackTransport::pair();
+        let mut entity = AndroidAutoEntity::new(head_unit);
+        let mut input_service = InputService::new();
+        input_service.set_supported_keycodes(&[button_code::Enum::HOME, button_code::Enum::BACK, button_code::Enum::SCROLL_WHEEL]);
+        entity.add_service(Box::new(input_service));
+        assert!(matches!(entity.send_rotary(1), Err(EntityError::NoInputChannel)));
+        let response = entity.create_service_discovery_response();
+        let supported_keycodes = response.channels[0].input_channel.supported_keycodes.clone();
+        assert_eq!(supported_keycodes, vec![0x03, 0x04, 0x10000]);
+        assert!(response.channels[0].input_channel.touch_screen_config.is_none());
+
+        assert!(unsupported_keycodes(&supported_keycodes, &[0x03, 0x10000]).is_empty());
+        assert_eq!(unsupported_keycodes(&supported_keycodes, &[0x03, 0x55]), vec![0x55]);
+
+        let events = [ButtonAction::Press, ButtonAction::LongPress, ButtonAction::Release]
+            .map(|action| button_event(button_code::Enum::HOME, action));
+        assert_eq!(events.clone().map(|event| (event.scan_code(), event.is_pressed(), event.long_press())), [(3, true, false), (3, true, true), (3, false, false)]);
+        let message = create_button_event_indication_message(ChannelID::from(6), events.to_vec()).unwrap();
+        let (message_id, indication) = message.decode::<InputMessageID, InputEventIndication>().unwrap();
+        assert_eq!(message_id, InputMessageID::INPUT_EVENT_INDICATION);
+        assert_eq!(indication.button_event.button_events.len(), 3);
+        assert!(indication.touch_event.is_none());
+
+        let message = create_relative_input_event_indication_message(ChannelID::from(6), vec![rotary_event(-2)]).unwrap();
+        let (_, indication) = message.decode::<InputMessageID, InputEventIndication>().unwrap();
+        let rotary = &indication.relative_input_event.relative_input_events[0];
+        assert_eq!((rotary.scan_code(), rotary.delta()), (keycode(button_code::Enum::SCROLL_WHEEL), -2));
+
+        let (message_id, binding_response) = create_binding_response_message(ChannelID::from(6), status::Enum::FAIL).unwrap()
+            .decode::<InputMessageID, BindingResponse>().unwrap();
+        assert_eq!(message_id, InputMessageID::BINDING_RESPONSE);
+        assert_eq!(binding_response.status(), status::Enum::FAIL);
+
+        //the phone binds keys on the input channel, only advertised keycodes are accepted
+        let (certificate, private_key) = test_identity();
+        let (head_unit, phone) = LoopbackTransport::pair();
+        let mut entity = AndroidAutoEntity::new(head_unit);
+        entity.set_identity(HeadUnitIdentity::new(certificate, private_key).unwrap());
+        let mut input_service = InputService::new();
+        input_service.set_supported_keycodes(&[button_code::Enum::HOME, button_code::Enum::BACK, button_code::Enum::SCROLL_WHEEL]);
+        entity.add_service(Box::new(input_service));
+        let events = entity.subscribe();
+        let mut phone = TestPhone::connect(&mut entity, phone);
+        assert!(matches!(events.try_recv().unwrap(), AndroidAutoEvent::VersionNegotiated(_)));
+        let input_channel = ChannelID::from(phone.discover(&mut entity).channels[0].channel_id() as u8);
+        assert!(entity.key_bindings().is_empty());
+
+        for (scan_codes, binding_status) in [(vec![0x03, 0x10000], status::Enum::OK), (vec![0x04, 0x55], status::Enum::FAIL)] {
+            let mut binding_request = BindingRequest::new();
+            binding_request.scan_codes = scan_codes.clone();
+            phone.send(input_channel, MessageType::Specific, InputMessageID::BINDING_REQUEST, &binding_request);
+            entity.poll().unwrap();
+            let message = phone.receive();
+            assert_eq!(message.channel_id, input_channel);
+            let (message_id, binding_response) = message.decode::<InputMessageID, BindingResponse>().unwrap();
+            assert_eq!(message_id, InputMessageID::BINDING_RESPONSE);
+            assert_eq!(binding_response.status(), binding_status);
+            let keycodes: Vec<u32> = scan_codes.iter().map(|scan_code| *scan_code as u32).collect();
+            assert_eq!(events.try_recv().unwrap(), AndroidAutoEvent::KeyBindingRequested(keycodes));
+        }
+        //the failed binding keeps the keys of the last successful one
+        assert_eq!(entity.key_bindings(), &[0x03, 0x10000]);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[cfg(feature = "evdev")]
+    #[test]
+    fn test_evdev_input() {
+        use evdev::{AbsoluteAxisType, EventType, InputEvent, Key, RelativeAxisType};
+        use crate::evdev_input::{EventMapper, InputAction, KeyMap};
+        use crate::input::{ButtonAction, TouchPoint};
+        use crate::protos::ButtonCodeEnum::button_code;
+
+        fn abs(axis: AbsoluteAxisType, value: i32) -> InputEvent {
+            InputEvent::new(EventType::ABSOLUTE, axis.0, value)
+        }
+        fn key(key: Key, value: i32) -> InputEvent {
+            InputEvent::new(EventType::KEY, key.code(), value)
+        }
+        let syn = InputEvent::new(EventType::SYNCHRONIZATION, 0, 0);
+
+        let mut key_map = KeyMap::new();
+        key_map.insert(Key::KEY_HOMEPAGE, button_code::Enum::HOME);
+        key_map.insert(Key::KEY_NEXTSONG, button_code::Enum::NEXT);
+        key_map.insert(Key::KEY_F13, button_code::Enum::HOME);
+        assert_eq!(key_map.button_codes(), vec![button_code::Enum::HOME, button_code::Enum::NEXT]);
+        assert_eq!(KeyMap::default().get(Key::KEY_PLAYPAUSE), Some(button_code::Enum::TOGGLE_PLAY));
+
+        let mut mapper = EventMapper::new(key_map.clone());
+        mapper.set_origin(0, 100);
+        let mut actions = Vec::new();
+        for event in [
+            //first finger down
+            abs(AbsoluteAxisType::ABS_MT_SLOT, 0), abs(AbsoluteAxisType::ABS_MT_TRACKING_ID, 10),
+            abs(AbsoluteAxisType::ABS_MT_POSITION_X, 20), abs(AbsoluteAxisType::ABS_MT_POSITION_Y, 130),
+            key(Key::BTN_TOUCH, 1), abs(AbsoluteAxisType::ABS_X, 20), abs(AbsoluteAxisType::ABS_Y, 130), syn,
+            //second finger down while the first one moves
+            abs(AbsoluteAxisType::ABS_MT_POSITION_X, 25),
+            abs(AbsoluteAxisType::ABS_MT_SLOT, 1), abs(AbsoluteAxisType::ABS_MT_TRACKING_ID, 11),
+            abs(AbsoluteAxisType::ABS_MT_POSITION_X, 300), abs(AbsoluteAxisType::ABS_MT_POSITION_Y, 150), syn,
+            //both up
+            abs(AbsoluteAxisType::ABS_MT_TRACKING_ID, -1), abs(AbsoluteAxisType::ABS_MT_SLOT, 0),
+            abs(AbsoluteAxisType::ABS_MT_TRACKING_ID, -1), key(Key::BTN_TOUCH, 0), syn,
+        ] {
+            actions.extend(mapper.handle(&event));
+        }
+        assert_eq!(actions, vec![
+            InputAction::TouchDown(TouchPoint::new(0, 20, 30)),
+            InputAction::TouchMove(vec![TouchPoint::new(0, 25, 30)]),
+            InputAction::TouchDown(TouchPoint::new(1, 300, 50)),
+            InputAction::TouchUp(0),
+            InputAction::TouchUp(1),
+        ]);
+
+        //single-touch panel, buttons held until the kernel repeats them and the rotary knob
+        let mut mapper = EventMapper::new(key_map);
+        let mut actions = Vec::new();
+        for event in [
+            key(Key::BTN_TOUCH, 1), abs(AbsoluteAxisType::ABS_X, 7), abs(AbsoluteAxisType::ABS_Y, 8), syn,
+            abs(AbsoluteAxisType::ABS_X, 9), syn,
+            key(Key::BTN_TOUCH, 0), syn,
+            key(Key::KEY_HOMEPAGE, 1), key(Key::KEY_HOMEPAGE, 2), key(Key::KEY_HOMEPAGE, 2), key(Key::KEY_HOMEPAGE, 0),
+            key(Key::KEY_VOLUMEUP, 1),
+            InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_DIAL.0, -2),
+        ] {
+            actions.extend(mapper.handle(&event));
+        }
+        assert_eq!(actions, vec![
+            InputAction::TouchDown(TouchPoint::new(0, 7, 8)),
+            InputAction::TouchMove(vec![TouchPoint::new(0, 9, 8)]),
+            InputAction::TouchUp(0),
+            InputAction::Button(button_code::Enum::HOME, ButtonAction::Press),
+            InputAction::Button(button_code::Enum::HOME, ButtonAction::LongPress),
+            InputAction::Button(button_code::Enum::HOME, ButtonAction::Release),
+            InputAction::Rotary(-2),
+        ]);
+
+    }
+
+    #[cfg(feature = "evdev")]
+    #[test]
+    #[ignore = "needs write access to /dev/uinput"]
+    fn test_evdev_uinput() {
+        use evdev::uinput::VirtualDeviceBuilder;
+        use evdev::{AbsInfo, AbsoluteAxisType, AttributeSet, EventType, InputEvent, Key, UinputAbsSetup};
+        use crate::evdev_input::{EvdevInput, InputAction, KeyMap};
+        use crate::input::{ButtonAction, TouchPoint};
+        use crate::protos::ButtonCodeEnum::button_code;
+
+        fn abs(axis: AbsoluteAxisType, value: i32) -> InputEvent {
+            InputEvent::new(EventType::ABSOLUTE, axis.0, value)
+        }
+
+        let mut key_map = KeyMap::new();
+        key_map.insert(Key::KEY_NEXTSONG, button_code::Enum::NEXT);
+        let builder = VirtualDeviceBuilder::new().expect("Unable to open /dev/uinput");
+        let keys: AttributeSet<Key> = [Key::KEY_NEXTSONG, Key::BTN_TOUCH].into_iter().collect();
+        let mut virtual_device = builder.name("rustyauto test panel").with_keys(&keys).unwrap()
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_MT_SLOT, AbsInfo::new(0, 0, 9, 0, 0, 0))).unwrap()
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_MT_TRACKING_ID, AbsInfo::new(0, 0, 65535, 0, 0, 0))).unwrap()
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_MT_POSITION_X, AbsInfo::new(0, 0, 1023, 0, 0, 0))).unwrap()
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_MT_POSITION_Y, AbsInfo::new(0, 0, 599, 0, 0, 0))).unwrap()
+            .build().unwrap();
+        let path = virtual_device.enumerate_dev_nodes_blocking().unwrap().next().unwrap().unwrap();
+        let mut evdev_input = EvdevInput::new(key_map);
+        //udev may not have created the device node yet
+        let mut attempts = 0;
+        while let Err(e) = evdev_input.open(&path) {
+            attempts += 1;
+            assert!(attempts < 50, "Unable to open {}: {}", path.display(), e);
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        assert_eq!(evdev_input.panel_size(), Some((1024, 600)));
+
+        virtual_device.emit(&[
+            abs(AbsoluteAxisType::ABS_MT_SLOT, 0), abs(AbsoluteAxisType::ABS_MT_TRACKING_ID, 1),
+            abs(AbsoluteAxisType::ABS_MT_POSITION_X, 512), abs(AbsoluteAxisType::ABS_MT_POSITION_Y, 300),
+        ]).unwrap();
+        virtual_device.emit(&[InputEvent::new(EventType::KEY, Key::KEY_NEXTSONG.code(), 1)]).unwrap();
+        let mut actions = Vec::new();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(2);
+        while actions.len() < 2 && std::time::Instant::now() < deadline {
+            match evdev_input.try_recv() {
+                Some(action) => actions.push(action),
+                None => std::thread::sleep(std::time::Duration::from_millis(10)),
+            }
+        }
+        assert_eq!(actions, vec![
+            InputAction::TouchDown(TouchPoint::new(0, 512, 300)),
+            InputAction::Button(button_code::Enum::NEXT, ButtonAction::Press),
+        ]);
+
+        //the device stays idle, dropping still stops its reader thread
+        let (dropped_tx, dropped_rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            drop(evdev_input);
+            dropped_tx.send(()).unwrap();
+        });
+        dropped_rx.recv_timeout(std::time::Duration::from_secs(1)).expect("Reader thread of an idle device did not stop");
+    }
 }
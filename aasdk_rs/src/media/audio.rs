@@ -0,0 +1,243 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::protos::AudioConfigData::AudioConfig;
+
+///Size of the RIFF and fmt chunks ahead of the samples
+const WAV_HEADER_SIZE: u32 = 44;
+
+///Most sample bytes a WAV file can hold, the RIFF size in the header is 32 bits
+const MAX_WAV_DATA_SIZE: u32 = u32::MAX - (WAV_HEADER_SIZE - 8);
+
+///Size of the largest fmt chunk, the one of WAVE_FORMAT_EXTENSIBLE
+const MAX_WAV_FMT_SIZE: u32 = 40;
+
+///Consumer of an audio stream played by the phone, e.g. the sound card or a capture file
+///
+///Buffers carry interleaved signed little-endian PCM samples in the format of the negotiated
+///configuration.
+pub trait AudioSink {
+    ///The phone starts a stream with the negotiated configuration
+    fn start(&mut self, config: &AudioConfig) -> std::io::Result<()> {
+        let _ = config;
+        Ok(())
+    }
+
+    ///PCM samples, `timestamp` is the presentation time in microseconds if the phone sent one
+    fn write(&mut self, timestamp: Option<u64>, pcm: &[u8]) -> std::io::Result<()>;
+
+    ///The phone stopped the stream or the session ended
+    fn stop(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    ///The Assistant started or stopped listening, e.g. to duck media while the user speaks
+    fn voice_session_changed(&mut self, active: bool) {
+        let _ = active;
+    }
+}
+
+///Sink shared between the application and an audio channel, it outlives single sessions
+pub type SharedAudioSink = Rc<RefCell<dyn AudioSink>>;
+
+///Writes the received samples into a WAV file
+///
+///Consecutive streams are appended, they have to use the same configuration. The sizes in the
+///header are updated whenever a stream stops, so the file is playable after every stream. Once the
+///file reaches the 4 GiB limit of RIFF, further samples are rejected.
+pub struct WavFileSink<W: Write + Seek> {
+    writer: W,
+    config: Option<AudioConfig>,
+    data_size: u32,
+}
+
+impl WavFileSink<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write + Seek> WavFileSink<W> {
+    pub fn new(writer: W) -> Self {
+        WavFileSink { writer, config: None, data_size: 0 }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    ///Bytes of samples written so far
+    pub fn data_size(&self) -> u32 {
+        self.data_size
+    }
+
+    fn write_header(&mut self, config: &AudioConfig) -> std::io::Result<()> {
+        let block_align = config.channel_count() * config.bit_depth() / 8;
+        self.writer.write_all(b"RIFF")?;
+        self.writer.write_all(&(WAV_HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.writer.write_all(b"WAVEfmt ")?;
+        self.writer.write_all(&16u32.to_le_bytes())?;
+        //format 1 is uncompressed PCM
+        self.writer.write_all(&1u16.to_le_bytes())?;
+        self.writer.write_all(&(config.channel_count() as u16).to_le_bytes())?;
+        self.writer.write_all(&config.sample_rate().to_le_bytes())?;
+        self.writer.write_all(&(config.sample_rate() * block_align).to_le_bytes())?;
+        self.writer.write_all(&(block_align as u16).to_le_bytes())?;
+        self.writer.write_all(&(config.bit_depth() as u16).to_le_bytes())?;
+        self.writer.write_all(b"data")?;
+        self.writer.write_all(&self.data_size.to_le_bytes())
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavFileSink<W> {
+    fn start(&mut self, config: &AudioConfig) -> std::io::Result<()> {
+        match &self.config {
+            None => {
+                self.write_header(config)?;
+                self.config = Some(config.clone());
+                Ok(())
+            }
+            Some(wav_config) if wav_config == config => Ok(()),
+            Some(_) => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "WAV file already holds samples of another audio configuration")),
+        }
+    }
+
+    fn write(&mut self, _timestamp: Option<u64>, pcm: &[u8]) -> std::io::Result<()> {
+        if self.config.is_none() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Samples received before the stream started"));
+        }
+        let data_size = u32::try_from(pcm.len()).ok()
+            .and_then(|length| self.data_size.checked_add(length))
+            .filter(|data_size| *data_size <= MAX_WAV_DATA_SIZE)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::FileTooLarge, "WAV file reached the 4 GiB size limit"))?;
+        self.writer.write_all(pcm)?;
+        self.data_size = data_size;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> std::io::Result<()> {
+        let config = match self.config.clone() {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header(&config)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+}
+
+///Provider of the microphone samples sent to the phone, e.g. the sound card or a recording
+///
+///Samples are interleaved signed little-endian PCM in the format advertised for the channel.
+pub trait AudioSource {
+    ///The phone opens the microphone
+    fn start(&mut self, config: &AudioConfig) -> std::io::Result<()> {
+        let _ = config;
+        Ok(())
+    }
+
+    ///Fill `buffer` with the samples available right now, returns the number of bytes written
+    ///
+    ///Must not block, 0 means that there is nothing to send at the moment.
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize>;
+
+    ///The phone closed the microphone or the session ended
+    fn stop(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    ///The Assistant started or stopped listening, e.g. to switch to a close-talk microphone
+    fn voice_session_changed(&mut self, active: bool) {
+        let _ = active;
+    }
+}
+
+///Source shared between the application and the AV input channel, it outlives single sessions
+pub type SharedAudioSource = Rc<RefCell<dyn AudioSource>>;
+
+///Plays the samples of a WAV file as microphone input, once
+pub struct WavFileSource<R: Read> {
+    reader: R,
+    config: AudioConfig,
+    ///Sample bytes not read yet
+    remaining: u32,
+}
+
+impl WavFileSource<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> WavFileSource<R> {
+    ///Read the header up to the samples, only uncompressed PCM is supported
+    pub fn new(mut reader: R) -> std::io::Result<Self> {
+        let mut riff_header = [0; 12];
+        reader.read_exact(&mut riff_header)?;
+        if &riff_header[0..4] != b"RIFF" || &riff_header[8..12] != b"WAVE" {
+            return Err(invalid_wav("not a RIFF WAVE file"));
+        }
+        let mut config = None;
+        loop {
+            let mut chunk_header = [0; 8];
+            reader.read_exact(&mut chunk_header)?;
+            let chunk_size = u32::from_le_bytes(chunk_header[4..8].try_into().unwrap());
+            match &chunk_header[0..4] {
+                b"fmt " => {
+                    if chunk_size > MAX_WAV_FMT_SIZE {
+                        return Err(invalid_wav("format chunk is too large"));
+                    }
+                    //chunks are padded to an even size, the pad byte is read along with the format
+                    let mut fmt = vec![0; chunk_size as usize + chunk_size as usize % 2];
+                    reader.read_exact(&mut fmt)?;
+                    if fmt.len() < 16 || u16::from_le_bytes([fmt[0], fmt[1]]) != 1 {
+                        return Err(invalid_wav("samples are not uncompressed PCM"));
+                    }
+                    let mut audio_config = AudioConfig::new();
+                    audio_config.set_channel_count(u16::from_le_bytes([fmt[2], fmt[3]]) as u32);
+                    audio_config.set_sample_rate(u32::from_le_bytes(fmt[4..8].try_into().unwrap()));
+                    audio_config.set_bit_depth(u16::from_le_bytes([fmt[14], fmt[15]]) as u32);
+                    config = Some(audio_config);
+                }
+                b"data" => {
+                    let config = config.ok_or_else(|| invalid_wav("samples ahead of the format"))?;
+                    return Ok(WavFileSource { reader, config, remaining: chunk_size });
+                }
+                //chunks are padded to an even size
+                _ => {
+                    let padded_size = chunk_size as u64 + chunk_size as u64 % 2;
+                    std::io::copy(&mut reader.by_ref().take(padded_size), &mut std::io::sink())?;
+                }
+            }
+        }
+    }
+
+    ///Format of the samples in the file
+    pub fn config(&self) -> &AudioConfig {
+        &self.config
+    }
+}
+
+impl<R: Read> AudioSource for WavFileSource<R> {
+    fn start(&mut self, config: &AudioConfig) -> std::io::Result<()> {
+        if *config != self.config {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "WAV file does not match the advertised audio configuration"));
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let length = buffer.len().min(self.remaining as usize);
+        let read = self.reader.read(&mut buffer[..length])?;
+        self.remaining -= read as u32;
+        Ok(read)
+    }
+}
+
+fn invalid_wav(reason: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Invalid WAV file: {}", reason))
+}
@@ -0,0 +1,5 @@
+pub mod audio;
+pub mod video;
+
+pub use self::audio::{AudioSink, AudioSource, SharedAudioSink, SharedAudioSource, WavFileSink, WavFileSource};
+pub use self::video::{H264FileSink, SharedVideoSink, VideoSink};
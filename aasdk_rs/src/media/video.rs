@@ -0,0 +1,95 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::protos::VideoConfigData::VideoConfig;
+
+///Start code separating the NAL units of an Annex-B stream
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+///Consumer of the H.264 stream projected by the phone, e.g. a decoder or a capture file
+///
+///The phone sends Annex-B data. Frames are handed over as received, a single call may carry
+///several NAL units.
+pub trait VideoSink {
+    ///The phone starts streaming with the negotiated configuration
+    fn start(&mut self, config: &VideoConfig) -> std::io::Result<()> {
+        let _ = config;
+        Ok(())
+    }
+
+    ///Codec configuration (SPS and PPS) needed to decode the following frames
+    fn codec_config(&mut self, data: &[u8]) -> std::io::Result<()>;
+
+    ///H.264 data, `timestamp` is the presentation time in microseconds if the phone sent one
+    fn frame(&mut self, timestamp: Option<u64>, data: &[u8]) -> std::io::Result<()>;
+
+    ///The phone stopped streaming or the session ended
+    fn stop(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+///Sink shared between the application and the video channel, it outlives single sessions
+pub type SharedVideoSink = Rc<RefCell<dyn VideoSink>>;
+
+///Writes the stream into an Annex-B `.h264` file, playable with e.g. `ffplay` or `mpv`
+pub struct H264FileSink<W: Write> {
+    writer: W,
+}
+
+impl H264FileSink<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> H264FileSink<W> {
+    pub fn new(writer: W) -> Self {
+        H264FileSink { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_annex_b(&mut self, data: &[u8]) -> std::io::Result<()> {
+        if !has_start_code(data) {
+            self.writer.write_all(&START_CODE)?;
+        }
+        self.writer.write_all(data)
+    }
+}
+
+impl<W: Write> VideoSink for H264FileSink<W> {
+    fn codec_config(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.write_annex_b(data)
+    }
+
+    fn frame(&mut self, _timestamp: Option<u64>, data: &[u8]) -> std::io::Result<()> {
+        self.write_annex_b(data)
+    }
+
+    fn stop(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn has_start_code(data: &[u8]) -> bool {
+    data.starts_with(&START_CODE) || data.starts_with(&START_CODE[1..])
+}
+
+///Whether the data starts with a sequence or picture parameter set
+pub fn is_codec_config(data: &[u8]) -> bool {
+    let nal_header = if data.starts_with(&START_CODE) {
+        data.get(4)
+    } else if data.starts_with(&START_CODE[1..]) {
+        data.get(3)
+    } else {
+        data.first()
+    };
+    //nal_unit_type 7 is the SPS, 8 the PPS
+    matches!(nal_header.map(|header| header & 0x1f), Some(7) | Some(8))
+}
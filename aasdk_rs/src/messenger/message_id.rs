@@ -0,0 +1,28 @@
+use protobuf::Enum;
+
+use crate::error::ProtocolError;
+
+pub use crate::protos::AVChannelMessageIdsEnum::avchannel_message::Enum as AVMessageID;
+pub use crate::protos::BluetoothChannelMessageIdsEnum::bluetooth_channel_message::Enum as BluetoothMessageID;
+pub use crate::protos::ControlMessageIdsEnum::control_message::Enum as ControlMessageID;
+pub use crate::protos::InputChannelMessageIdsEnum::input_channel_message::Enum as InputMessageID;
+pub use crate::protos::SensorChannelMessageIdsEnum::sensor_channel_message::Enum as SensorMessageID;
+
+///Message ids of a channel, generated from the *MessageIdsEnum protos
+///
+///The id is the 16 bit word in front of every message payload.
+pub trait MessageID: Enum {
+    fn from_word(message_id_word: u16) -> Result<Self, ProtocolError> {
+        Self::from_i32(message_id_word as i32).ok_or(ProtocolError::UnknownMessageId(message_id_word))
+    }
+
+    fn word(&self) -> u16 {
+        self.value() as u16
+    }
+}
+
+impl MessageID for AVMessageID {}
+impl MessageID for BluetoothMessageID {}
+impl MessageID for ControlMessageID {}
+impl MessageID for InputMessageID {}
+impl MessageID for SensorMessageID {}
@@ -3,11 +3,21 @@ use std::sync::mpsc::Receiver;
 use std::time::Duration;
 use std::u16;
 
-use crate::channels;
+use crate::channels::ChannelRegistry;
+use crate::channels::control_service_channel;
+use crate::cryptor::Cryptor;
+use crate::error::{MessengerError, ProtocolError};
 use crate::messenger::EncryptionType::{Encrypted, Plain};
 use crate::messenger::FrameType::{Bulk, First, Last, Middle};
 use crate::messenger::MessageType::{Control, Specific};
-use crate::usbdriver::UsbDriver;
+use crate::messenger::message_id::{ControlMessageID, MessageID};
+use crate::messenger::reassembler::{FrameBuffer, MessageReassembler};
+use crate::transport::{Transport, TransportError};
+
+///Payload size at which outgoing messages are split into several frames
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 0x4000;
+///Size of a single read from the transport
+const RECEIVE_BUFFER_SIZE: usize = 10000;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum EncryptionType {
@@ -90,129 +100,338 @@ pub struct Message {
 }
 
 impl Message {
+    ///Encode the message as a single frame
+    ///
+    ///The frame size field is only 16 bits wide, use `to_frames` for payloads that may be larger.
     pub fn to_byte_vector(self) -> Vec<u8> {
-        let mut byte_vector = vec![self.channel_id as u8];
-        let payload_length = self.payload.len();
-        let lower_byte = payload_length as u8;
-        let upper_byte = (payload_length >> 8) as u8;
-        byte_vector.push(self.frame_header.to_byte());
-        byte_vector.push(upper_byte);
-        byte_vector.push(lower_byte);
-        byte_vector.extend(self.payload);
-        //buffer = byte_vector.as_slice();
-        return byte_vector;
-    }
-
-    pub fn from_data_frame(data_frame: &[u8]) -> Self {
+        if self.payload.len() > u16::MAX as usize {
+            log::error!("Payload of {} bytes does not fit into a single frame, it will be truncated", self.payload.len());
+        }
+        encode_frame(self.channel_id, self.frame_header, None, self.payload.as_slice())
+    }
+
+    ///Encode the message, splitting it into First/Middle/Last frames if the payload is larger
+    ///than `max_frame_size`
+    pub fn to_frames(self, max_frame_size: usize) -> Vec<Vec<u8>> {
+        let never_fails: Result<_, std::convert::Infallible> = self.to_frames_with(max_frame_size, |chunk| Ok(chunk.to_vec()));
+        never_fails.unwrap()
+    }
+
+    ///Like `to_frames`, but every frame payload is passed through `transform` (e.g. encryption)
+    ///before it is written; the total size on First frames stays the untransformed size.
+    pub fn to_frames_with<E>(self, max_frame_size: usize, mut transform: impl FnMut(&[u8]) -> Result<Vec<u8>, E>) -> Result<Vec<Vec<u8>>, E> {
+        let max_frame_size = max_frame_size.clamp(1, u16::MAX as usize);
+        if self.payload.len() <= max_frame_size {
+            let payload = transform(self.payload.as_slice())?;
+            return Ok(vec![encode_frame(self.channel_id, self.frame_header, None, payload.as_slice())]);
+        }
+        let total_size = self.payload.len() as u32;
+        let chunks: Vec<&[u8]> = self.payload.chunks(max_frame_size).collect();
+        let last_index = chunks.len() - 1;
+        chunks.into_iter().enumerate().map(|(index, chunk)| {
+            let frame_type = match index {
+                0 => First,
+                _ if index == last_index => Last,
+                _ => Middle,
+            };
+            let frame_header = FrameHeader { frame_type, ..self.frame_header };
+            let total_size = if frame_type == First { Some(total_size) } else { None };
+            Ok(encode_frame(self.channel_id, frame_header, total_size, transform(chunk)?.as_slice()))
+        }).collect()
+    }
+
+    pub fn from_data_frame(data_frame: &[u8]) -> Result<Self, ProtocolError> {
         //log::debug!("Processing data_frame: {:?}", data_frame);
+        if data_frame.len() < 4 {
+            return Err(ProtocolError::TruncatedHeader(data_frame.len()));
+        }
+        let declared_length = u16::from_be_bytes([data_frame[2], data_frame[3]]) as usize;
         let payload_slice = &data_frame[4..];
+        if declared_length != payload_slice.len() {
+            return Err(ProtocolError::LengthMismatch { declared: declared_length, actual: payload_slice.len() });
+        }
         let to_return = Self {
             frame_header: FrameHeader::from(data_frame[1]),
             channel_id: ChannelID::from(data_frame[0]),
             payload: payload_slice.to_vec(),
         };
         //log::debug!("Message: {:?}", to_return);
-        to_return
+        Ok(to_return)
     }
 
-    pub fn handle(&self) {
-        /*if self.frame_header.encryption_type == EncryptionType::Encrypted {
-            log::info!("Encrypted message, decrypting now");
-        } else {*/
-        log::debug!("Channel ID: {:?}", self.channel_id);
-        match self.channel_id {
-            ChannelID::Control => { channels::control_service_channel::handle_message(self) }
-            ChannelID::AVInput => { channels::av_input_service_channel::handle_message(self) }
-            ChannelID::MediaAudio => { channels::media_audio_service_channel::handle_message(self) }
-            ChannelID::SpeechAudio => { channels::speech_audio_service_channel::handle_message(self) }
-            ChannelID::SystemAudio => { channels::system_audio_service_channel::handle_message(self) }
-            ChannelID::Sensor => { channels::sensor_service_channel::handle_message(self) }
-            ChannelID::Video => { channels::video_service_channel::handle_message(self) }
-            ChannelID::Input => { channels::input_service_channel::handle_message(self) }
-            _ => { todo!() }
+    ///Message id in the first two bytes of the payload
+    pub fn message_id(&self) -> Result<u16, ProtocolError> {
+        match self.payload.as_slice() {
+            [upper, lower, ..] => Ok(u16::from_be_bytes([*upper, *lower])),
+            _ => Err(ProtocolError::MissingMessageId),
         }
-        //}
+    }
+
+    ///Message id as one of the id enums of the channel
+    pub fn typed_message_id<I: MessageID>(&self) -> Result<I, ProtocolError> {
+        I::from_word(self.message_id()?)
+    }
+
+    ///Payload following the message id
+    pub fn body(&self) -> &[u8] {
+        self.payload.get(2..).unwrap_or_default()
+    }
+
+    ///Decode the message id and the protobuf message following it
+    pub fn decode<I: MessageID, M: protobuf::Message>(&self) -> Result<(I, M), ProtocolError> {
+        let message_id = self.typed_message_id()?;
+        Ok((message_id, M::parse_from_bytes(self.body())?))
+    }
+
+    ///Build a single frame message from a message id and a protobuf body
+    ///
+    ///Fails if the body misses required fields, e.g. in a configuration supplied by the application.
+    pub fn from_proto<I: MessageID, M: protobuf::Message>(channel_id: ChannelID, encryption_type: EncryptionType, message_type: MessageType, message_id: I, body: &M) -> Result<Self, ProtocolError> {
+        let mut payload = message_id.word().to_be_bytes().to_vec();
+        payload.extend(body.write_to_bytes().map_err(ProtocolError::Encode)?);
+        Ok(Message {
+            frame_header: FrameHeader { encryption_type, message_type, frame_type: Bulk },
+            channel_id,
+            payload,
+        })
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub enum ChannelID {
-    Control = 0,
-    Input = 1,
-    Sensor = 2,
-    Video = 3,
-    MediaAudio = 4,
-    SpeechAudio = 5,
-    SystemAudio = 6,
-    AVInput = 7,
-    Bluetooth = 8,
-    None = 255,
+fn encode_frame(channel_id: ChannelID, frame_header: FrameHeader, total_size: Option<u32>, payload: &[u8]) -> Vec<u8> {
+    let mut byte_vector = vec![channel_id.into(), frame_header.to_byte()];
+    byte_vector.extend((payload.len() as u16).to_be_bytes());
+    if let Some(total_size) = total_size {
+        byte_vector.extend(total_size.to_be_bytes());
+    }
+    byte_vector.extend(payload);
+    byte_vector
+}
+
+///Channel a frame belongs to
+///
+///Apart from the control channel, ids are not fixed by the protocol: the head unit assigns them
+///to its services when answering the service discovery request. The named ids are the ones
+///used by head units with a fixed service layout.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChannelID(pub u8);
+
+#[allow(non_upper_case_globals)]
+impl ChannelID {
+    pub const Control: ChannelID = ChannelID(0);
+    pub const Input: ChannelID = ChannelID(1);
+    pub const Sensor: ChannelID = ChannelID(2);
+    pub const Video: ChannelID = ChannelID(3);
+    pub const MediaAudio: ChannelID = ChannelID(4);
+    pub const SpeechAudio: ChannelID = ChannelID(5);
+    pub const SystemAudio: ChannelID = ChannelID(6);
+    pub const AVInput: ChannelID = ChannelID(7);
+    pub const Bluetooth: ChannelID = ChannelID(8);
 }
 
 impl From<u8> for ChannelID {
     fn from(channel_id_as_byte: u8) -> Self {
-        match channel_id_as_byte {
-            0 => ChannelID::Control,
-            1 => ChannelID::Input,
-            2 => ChannelID::Sensor,
-            3 => ChannelID::Video,
-            4 => ChannelID::MediaAudio,
-            5 => ChannelID::SpeechAudio,
-            6 => ChannelID::SystemAudio,
-            7 => ChannelID::AVInput,
-            8 => ChannelID::Bluetooth,
-            _ => ChannelID::None
-        }
+        ChannelID(channel_id_as_byte)
     }
 }
 
-pub struct LegacyMessenger {
-    usb_driver: UsbDriver,
+impl From<ChannelID> for u8 {
+    fn from(channel_id: ChannelID) -> Self {
+        channel_id.0
+    }
 }
 
-impl LegacyMessenger {
-    pub fn init(usb_driver: UsbDriver) -> Self {
-        LegacyMessenger { usb_driver }
+impl From<ChannelID> for u32 {
+    fn from(channel_id: ChannelID) -> Self {
+        channel_id.0 as u32
     }
-    pub fn receive_message(&self, size: usize) -> Message {
+}
+
+pub struct LegacyMessenger<T: Transport> {
+    transport: T,
+    timeout: Duration,
+}
+
+impl<T: Transport> LegacyMessenger<T> {
+    pub fn init(transport: T) -> Self {
+        LegacyMessenger { transport, timeout: Duration::from_secs(crate::constants::USB_TIMEOUT_SECONDS as u64) }
+    }
+    pub fn receive_message(&self, size: usize) -> Result<Message, MessengerError> {
         let mut in_buffer = vec![0u8; size];
-        self.usb_driver.read_buffer(in_buffer.as_mut_slice());
-        let received_message = Message::from_data_frame(in_buffer.as_slice());
-        received_message
+        let read_size = self.transport.read_buffer(in_buffer.as_mut_slice(), self.timeout)?;
+        let received_message = Message::from_data_frame(&in_buffer[..read_size])?;
+        Ok(received_message)
     }
-    pub fn send_message(&mut self, message_to_send: Message) {
-        self.usb_driver.send_buffer(message_to_send.to_byte_vector().as_slice());
+    pub fn send_message(&mut self, message_to_send: Message) -> Result<(), TransportError> {
+        for frame in message_to_send.to_frames(DEFAULT_MAX_FRAME_SIZE) {
+            self.transport.send_buffer(frame.as_slice(), self.timeout)?;
+        }
+        Ok(())
     }
 }
 
-pub struct Messenger {
+pub struct Messenger<T: Transport> {
     //in_queue: Receiver<Message>,
     //out_queue: Receiver<Message>,
-    usb_driver: UsbDriver,
+    transport: T,
+    timeout: Duration,
+    max_frame_size: usize,
+    frame_buffer: FrameBuffer,
+    reassembler: MessageReassembler,
+    cryptor: Option<Cryptor>,
 }
 
-impl Messenger {
-    pub fn init(usb_driver: UsbDriver) -> Self {
-        Messenger { usb_driver }
+impl<T: Transport> Messenger<T> {
+    pub fn init(transport: T) -> Self {
+        Messenger {
+            transport,
+            timeout: Duration::from_secs(crate::constants::USB_TIMEOUT_SECONDS as u64),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            frame_buffer: FrameBuffer::new(),
+            reassembler: MessageReassembler::new(),
+            cryptor: None,
+        }
+    }
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+    ///Largest payload put into a single frame before a message gets fragmented
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
+    }
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+    ///Drop the session state and release the transport
+    pub fn close(&mut self) -> Result<(), TransportError> {
+        self.cryptor = None;
+        self.frame_buffer = FrameBuffer::new();
+        self.reassembler = MessageReassembler::new();
+        self.transport.close()
+    }
+    pub fn set_cryptor(&mut self, cryptor: Cryptor) {
+        self.cryptor = Some(cryptor);
     }
-    pub fn run(&mut self, in_rx: &Receiver<i32>, out_rx: &Receiver<Message>) {
+    ///True once the TLS handshake with the phone is finished
+    pub fn is_authenticated(&self) -> bool {
+        self.cryptor.as_ref().is_some_and(Cryptor::is_active)
+    }
+    pub fn cryptor(&self) -> Option<&Cryptor> {
+        self.cryptor.as_ref()
+    }
+    ///Send the first TLS handshake records to the phone
+    pub fn start_handshake(&mut self) -> Result<(), MessengerError> {
+        log::info!("Starting TLS handshake");
+        self.continue_handshake(&[])
+    }
+    fn continue_handshake(&mut self, handshake_buffer: &[u8]) -> Result<(), MessengerError> {
+        let cryptor = self.cryptor.as_mut().ok_or(MessengerError::NoCryptor)?;
+        cryptor.write_handshake_buffer(handshake_buffer);
+        let finished = cryptor.do_handshake()?;
+        let outgoing = cryptor.read_handshake_buffer();
+        if !outgoing.is_empty() {
+            self.send_message(control_service_channel::create_ssl_handshake_message(outgoing.as_slice()))?;
+        }
+        if finished {
+            let mut auth_complete_indication = crate::protos::AuthCompleteIndicationMessage::AuthCompleteIndication::new();
+            auth_complete_indication.set_status(crate::protos::StatusEnum::status::Enum::OK);
+            self.send_message(control_service_channel::create_auth_complete_message(auth_complete_indication)?)?;
+        }
+        Ok(())
+    }
+    ///Consume SSL_HANDSHAKE messages from the phone, returns false for every other message
+    fn handle_handshake_message(&mut self, message: &Message) -> Result<bool, MessengerError> {
+        if message.channel_id != ChannelID::Control || !matches!(message.typed_message_id(), Ok(ControlMessageID::SSL_HANDSHAKE)) {
+            return Ok(false);
+        }
+        log::debug!("Received TLS handshake message");
+        self.continue_handshake(&message.payload[2..])?;
+        Ok(true)
+    }
+    pub fn send_message(&mut self, message_to_send: Message) -> Result<(), MessengerError> {
+        let frames = match message_to_send.frame_header.encryption_type {
+            Plain => message_to_send.to_frames(self.max_frame_size),
+            Encrypted => {
+                let cryptor = self.cryptor.as_mut().ok_or(MessengerError::NoCryptor)?;
+                //keep every encrypted frame within a single TLS record
+                message_to_send.to_frames_with(self.max_frame_size.min(DEFAULT_MAX_FRAME_SIZE), |chunk| cryptor.encrypt(chunk))?
+            }
+        };
+        for frame in frames {
+            self.transport.send_buffer(frame.as_slice(), self.timeout)?;
+        }
+        Ok(())
+    }
+    pub fn run(&mut self, in_rx: &Receiver<i32>, out_rx: &Receiver<Message>, channels: &mut ChannelRegistry) {
         //log::debug!("Running");
         if let Ok(message_to_send) = out_rx.try_recv() {
             log::debug!("Received message to send!");
-            self.usb_driver.send_buffer(message_to_send.to_byte_vector().as_slice());
+            if let Err(e) = self.send_message(message_to_send) {
+                log::error!("Error sending message: {}", e);
+            }
         }
         match in_rx.try_recv() {
             Ok(message_to_receive) => {
                 log::debug!("Received message to recv!");
-                let mut in_buffer = vec![0u8; 10000];
-                self.usb_driver.read_buffer(in_buffer.as_mut_slice());
-                let received_message = Message::from_data_frame(in_buffer.as_slice());
-                received_message.handle();
+                match self.receive_messages() {
+                    Ok(received_messages) => {
+                        for received_message in received_messages {
+                            if let Err(e) = channels.dispatch(&received_message) {
+                                log::error!("Dropping message on channel {:?}: {}", received_message.channel_id, e);
+                            }
+                        }
+                    }
+                    Err(e) => log::error!("Error receiving message: {}", e),
+                }
             }
             Err(e) => {
                 if e != std::sync::mpsc::TryRecvError::Empty { log::error!("Error receiving on in_rx: {}",e); }
             }
         }
+    }
+    ///Read once from the transport and return the complete messages that arrived
+    ///
+    ///TLS handshake messages are consumed here, a read timeout just yields no messages.
+    pub fn receive_messages(&mut self) -> Result<Vec<Message>, MessengerError> {
+        let mut in_buffer = vec![0u8; RECEIVE_BUFFER_SIZE];
+        let size = match self.transport.read_buffer(in_buffer.as_mut_slice(), self.timeout) {
+            Ok(size) => size,
+            Err(TransportError::Timeout) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut received_messages = Vec::new();
+        for received_message in self.receive_bytes(&in_buffer[..size]) {
+            if !self.handle_handshake_message(&received_message)? {
+                received_messages.push(received_message);
+            }
+        }
+        Ok(received_messages)
+    }
+    ///Split freshly read bytes into frames and return every message they completed
+    pub fn receive_bytes(&mut self, bytes: &[u8]) -> Vec<Message> {
+        self.frame_buffer.push_bytes(bytes);
+        let mut received_messages = Vec::new();
+        while let Some(mut frame) = self.frame_buffer.next_frame() {
+            //frames are encrypted one by one, so they have to be decrypted before reassembly
+            if frame.frame_header.encryption_type == Encrypted {
+                let decrypted = match self.cryptor.as_mut() {
+                    Some(cryptor) => cryptor.decrypt(frame.payload.as_slice()).map_err(ProtocolError::from),
+                    None => Err(ProtocolError::UnexpectedEncryption),
+                };
+                match decrypted {
+                    Ok(payload) => frame.payload = payload,
+                    Err(e) => {
+                        log::error!("Dropping frame: {}", e);
+                        continue;
+                    }
+                }
+            }
+            match self.reassembler.push_frame(frame) {
+                Ok(Some(message)) => received_messages.push(message),
+                Ok(None) => {}
+                Err(e) => log::error!("Dropping frame: {}", e),
+            }
+        }
+        received_messages
     }/*
     pub fn enqueue_receive(&mut self, to_receive: Message) {
         self.in_queue.push_back(to_receive);
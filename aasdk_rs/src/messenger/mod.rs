@@ -0,0 +1,7 @@
+#[allow(clippy::module_inception)]
+mod messenger;
+pub mod message_id;
+pub mod reassembler;
+
+pub use self::messenger::*;
+pub use self::message_id::*;
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use crate::error::ProtocolError;
+use crate::messenger::{ChannelID, FrameHeader, FrameType, Message};
+
+///Size of the common frame header: channel id, flags and 16 bit frame payload size
+const FRAME_HEADER_SIZE: usize = 4;
+///First frames of a fragmented message carry the 32 bit total message size after the header
+const TOTAL_SIZE_FIELD_SIZE: usize = 4;
+
+///A single frame as it was read from the transport
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub frame_header: FrameHeader,
+    pub channel_id: ChannelID,
+    ///Only present on First frames
+    pub total_size: Option<u32>,
+    pub payload: Vec<u8>,
+}
+
+///Collects raw bytes from the transport and cuts them into frames
+///
+///A single read may contain several frames, or only part of one; incomplete frames stay
+///buffered until the rest arrives with a later read.
+#[derive(Default)]
+pub struct FrameBuffer {
+    buffer: Vec<u8>,
+}
+
+impl FrameBuffer {
+    pub fn new() -> Self {
+        FrameBuffer { buffer: Vec::new() }
+    }
+
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    ///Take the next complete frame out of the buffer, if one has fully arrived
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        if self.buffer.len() < FRAME_HEADER_SIZE {
+            return None;
+        }
+        let frame_header = FrameHeader::from(self.buffer[1]);
+        let payload_size = u16::from_be_bytes([self.buffer[2], self.buffer[3]]) as usize;
+        let mut offset = FRAME_HEADER_SIZE;
+        let mut total_size = None;
+        if frame_header.frame_type == FrameType::First {
+            if self.buffer.len() < offset + TOTAL_SIZE_FIELD_SIZE {
+                return None;
+            }
+            total_size = Some(u32::from_be_bytes([self.buffer[4], self.buffer[5], self.buffer[6], self.buffer[7]]));
+            offset += TOTAL_SIZE_FIELD_SIZE;
+        }
+        if self.buffer.len() < offset + payload_size {
+            return None;
+        }
+        let channel_id = ChannelID::from(self.buffer[0]);
+        let payload = self.buffer[offset..offset + payload_size].to_vec();
+        self.buffer.drain(..offset + payload_size);
+        Some(Frame { frame_header, channel_id, total_size, payload })
+    }
+}
+
+struct PartialMessage {
+    frame_header: FrameHeader,
+    total_size: usize,
+    payload: Vec<u8>,
+}
+
+///Stitches First/Middle/Last frames back into complete messages, separately for every channel
+#[derive(Default)]
+pub struct MessageReassembler {
+    partial_messages: HashMap<ChannelID, PartialMessage>,
+}
+
+impl MessageReassembler {
+    pub fn new() -> Self {
+        MessageReassembler { partial_messages: HashMap::new() }
+    }
+
+    ///Feed the next frame, returning the message once it is complete
+    ///
+    ///Frames that do not fit the message in progress (unexpected frame type, declared sizes
+    ///not matching the received payload) are rejected and the message in progress is dropped.
+    pub fn push_frame(&mut self, frame: Frame) -> Result<Option<Message>, ProtocolError> {
+        let channel_id = frame.channel_id;
+        match frame.frame_header.frame_type {
+            FrameType::Bulk => {
+                if self.partial_messages.remove(&channel_id).is_some() {
+                    log::error!("Bulk frame on channel {:?} interrupted a fragmented message, dropping it", channel_id);
+                }
+                Ok(Some(Message {
+                    frame_header: frame.frame_header,
+                    channel_id,
+                    payload: frame.payload,
+                }))
+            }
+            FrameType::First => {
+                if self.partial_messages.remove(&channel_id).is_some() {
+                    log::error!("First frame on channel {:?} interrupted a fragmented message, dropping it", channel_id);
+                }
+                let total_size = frame.total_size.unwrap_or_default() as usize;
+                if frame.payload.len() > total_size {
+                    return Err(ProtocolError::LengthMismatch { declared: total_size, actual: frame.payload.len() });
+                }
+                self.partial_messages.insert(channel_id, PartialMessage {
+                    frame_header: frame.frame_header,
+                    total_size,
+                    payload: frame.payload,
+                });
+                Ok(None)
+            }
+            FrameType::Middle | FrameType::Last => {
+                let mut partial_message = match self.partial_messages.remove(&channel_id) {
+                    Some(partial_message) => partial_message,
+                    None => return Err(ProtocolError::UnexpectedFrame { channel_id, frame_type: frame.frame_header.frame_type }),
+                };
+                partial_message.payload.extend(frame.payload);
+                if partial_message.payload.len() > partial_message.total_size {
+                    return Err(ProtocolError::LengthMismatch { declared: partial_message.total_size, actual: partial_message.payload.len() });
+                }
+                if frame.frame_header.frame_type == FrameType::Middle {
+                    self.partial_messages.insert(channel_id, partial_message);
+                    return Ok(None);
+                }
+                if partial_message.payload.len() != partial_message.total_size {
+                    return Err(ProtocolError::LengthMismatch { declared: partial_message.total_size, actual: partial_message.payload.len() });
+                }
+                Ok(Some(Message {
+                    frame_header: FrameHeader {
+                        frame_type: FrameType::Bulk,
+                        ..partial_message.frame_header
+                    },
+                    channel_id,
+                    payload: partial_message.payload,
+                }))
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+///Interval between two head unit pings
+pub const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(5);
+///Time without a ping response after which the phone is considered gone
+pub const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(15);
+
+///What the session has to do to keep the phone alive
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PingAction {
+    Idle,
+    ///Send a PING_REQUEST carrying this timestamp
+    Send(i64),
+    ///The phone did not answer for longer than the timeout
+    TimedOut,
+}
+
+///Keep-alive of the session
+///
+///Sends a ping every `interval` and expects the phone to echo the timestamp in a PING_RESPONSE.
+///Time is passed in by the caller, so the pinger never blocks or spawns anything.
+pub struct Pinger {
+    interval: Duration,
+    timeout: Duration,
+    last_sent: Option<Instant>,
+    last_response: Option<Instant>,
+    outstanding: Vec<(i64, Instant)>,
+    round_trip_time: Option<Duration>,
+}
+
+impl Pinger {
+    pub fn new(interval: Duration, timeout: Duration) -> Self {
+        Pinger {
+            interval,
+            timeout,
+            last_sent: None,
+            last_response: None,
+            outstanding: Vec::new(),
+            round_trip_time: None,
+        }
+    }
+
+    ///Forget previous pings, the timeout counts from `now`
+    pub fn start(&mut self, now: Instant) {
+        self.last_sent = None;
+        self.last_response = Some(now);
+        self.outstanding.clear();
+        self.round_trip_time = None;
+    }
+
+    pub fn stop(&mut self) {
+        self.last_response = None;
+        self.outstanding.clear();
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.last_response.is_some()
+    }
+
+    pub fn poll(&mut self, now: Instant) -> PingAction {
+        let last_response = match self.last_response {
+            Some(last_response) => last_response,
+            None => return PingAction::Idle,
+        };
+        if now.saturating_duration_since(last_response) > self.timeout {
+            log::error!("No ping response for {:?}", now.saturating_duration_since(last_response));
+            return PingAction::TimedOut;
+        }
+        if self.last_sent.is_some_and(|last_sent| now.saturating_duration_since(last_sent) < self.interval) {
+            return PingAction::Idle;
+        }
+        let mut timestamp = timestamp_micros();
+        //two pings within the same microsecond would not be told apart
+        if let Some((last_timestamp, _)) = self.outstanding.last() {
+            timestamp = timestamp.max(last_timestamp + 1);
+        }
+        self.last_sent = Some(now);
+        self.outstanding.push((timestamp, now));
+        PingAction::Send(timestamp)
+    }
+
+    ///Match a PING_RESPONSE to the ping it answers, returns the round trip time
+    pub fn handle_response(&mut self, timestamp: i64, now: Instant) -> Option<Duration> {
+        let index = match self.outstanding.iter().position(|(sent_timestamp, _)| *sent_timestamp == timestamp) {
+            Some(index) => index,
+            None => {
+                log::debug!("Ping response with unknown timestamp {}", timestamp);
+                return None;
+            }
+        };
+        let (_, sent) = self.outstanding[index];
+        //older pings are answered in order, if at all
+        self.outstanding.drain(..=index);
+        self.last_response = Some(now);
+        let round_trip_time = now.saturating_duration_since(sent);
+        log::debug!("Ping round trip time {:?}", round_trip_time);
+        self.round_trip_time = Some(round_trip_time);
+        self.round_trip_time
+    }
+
+    ///Round trip time of the last answered ping
+    pub fn round_trip_time(&self) -> Option<Duration> {
+        self.round_trip_time
+    }
+}
+
+impl Default for Pinger {
+    fn default() -> Self {
+        Self::new(DEFAULT_PING_INTERVAL, DEFAULT_PING_TIMEOUT)
+    }
+}
+
+///Timestamp carried in pings, microseconds since the unix epoch
+pub fn timestamp_micros() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_micros() as i64).unwrap_or_default()
+}
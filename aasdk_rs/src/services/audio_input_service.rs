@@ -1,9 +1,38 @@
+use crate::channels::av_input_service_channel::{default_microphone_config, AVInputServiceChannel};
+use crate::channels::ChannelHandler;
+use crate::media::audio::SharedAudioSource;
+use crate::messenger::ChannelID;
 use crate::services::service::Service;
+use crate::protos::AudioConfigData::AudioConfig;
+use crate::protos::AVInputChannelData::AVInputChannel;
+use crate::protos::AVStreamTypeEnum::avstream_type;
 use crate::protos::ServiceDiscoveryResponseMessage::ServiceDiscoveryResponse;
 
-pub struct InputService {}
+///Microphone of the head unit, used by the phone for calls and the Assistant
+pub struct AudioInputService {
+    config: AudioConfig,
+    source: Option<SharedAudioSource>,
+}
+
+impl AudioInputService {
+    ///Service advertising `config` as the format of the microphone samples
+    pub fn new(config: AudioConfig) -> Self {
+        AudioInputService { config, source: None }
+    }
 
-impl Service for InputService {
+    ///Provider of the microphone samples, kept across sessions
+    pub fn set_source(&mut self, source: SharedAudioSource) {
+        self.source = Some(source);
+    }
+}
+
+impl Default for AudioInputService {
+    fn default() -> Self {
+        Self::new(default_microphone_config())
+    }
+}
+
+impl Service for AudioInputService {
     fn start(&self) {
         log::info!("Start");
     }
@@ -20,15 +49,25 @@ impl Service for InputService {
         log::info!("Resume");
     }
 
-    fn fill_features(&self, response: &mut ServiceDiscoveryResponse) {
+    fn fill_features(&self, channel_id: ChannelID, response: &mut ServiceDiscoveryResponse) {
         log::info!("Fill Features");
 
         let mut channel_descriptor = crate::protos::ChannelDescriptorData::ChannelDescriptor::default();
-        channel_descriptor.set_channel_id(crate::messenger::ChannelID::Input as u32);
-
-        dbg!(channel_descriptor.clone());
-
+        channel_descriptor.set_channel_id(channel_id.into());
+        let mut av_input_channel = AVInputChannel::new();
+        av_input_channel.set_stream_type(avstream_type::Enum::AUDIO);
+        av_input_channel.audio_config = Some(self.config.clone()).into();
+        channel_descriptor.av_input_channel = Some(av_input_channel).into();
+        log::debug!("Audio input channel descriptor: {:?}", channel_descriptor);
 
         response.channels.push(channel_descriptor);
     }
+
+    fn channel_handler(&self) -> Option<Box<dyn ChannelHandler>> {
+        let mut channel = AVInputServiceChannel::new(self.config.clone());
+        if let Some(source) = &self.source {
+            channel.set_source(source.clone());
+        }
+        Some(Box::new(channel))
+    }
 }
@@ -0,0 +1,87 @@
+use crate::channels::audio_service_channel::{default_audio_config, AudioServiceChannel, DEFAULT_AUDIO_MAX_UNACKED};
+use crate::channels::ChannelHandler;
+use crate::media::audio::SharedAudioSink;
+use crate::messenger::ChannelID;
+use crate::services::service::Service;
+use crate::protos::AudioConfigData::AudioConfig;
+use crate::protos::AudioTypeEnum::audio_type;
+use crate::protos::AVChannelData::AVChannel;
+use crate::protos::AVStreamTypeEnum::avstream_type;
+use crate::protos::ServiceDiscoveryResponseMessage::ServiceDiscoveryResponse;
+
+///Audio played by the phone, one service per audio type: media, speech (navigation prompts and
+///the Assistant) or system sounds
+pub struct AudioService {
+    audio_type: audio_type::Enum,
+    config: AudioConfig,
+    max_unacked: u32,
+    sink: Option<SharedAudioSink>,
+}
+
+impl AudioService {
+    ///Service advertising `config` as the only configuration of the `audio_type` channel
+    pub fn new(audio_type: audio_type::Enum, config: AudioConfig) -> Self {
+        AudioService { audio_type, config, max_unacked: DEFAULT_AUDIO_MAX_UNACKED, sink: None }
+    }
+
+    ///Service with the usual configuration of `audio_type`
+    pub fn with_default_config(audio_type: audio_type::Enum) -> Self {
+        Self::new(audio_type, default_audio_config(audio_type))
+    }
+
+    pub fn audio_type(&self) -> audio_type::Enum {
+        self.audio_type
+    }
+
+    ///Buffers the phone may send before it has to wait for an acknowledgement
+    pub fn set_max_unacked(&mut self, max_unacked: u32) {
+        self.max_unacked = max_unacked;
+    }
+
+    ///Receiver of the played samples, kept across sessions
+    pub fn set_sink(&mut self, sink: SharedAudioSink) {
+        self.sink = Some(sink);
+    }
+}
+
+impl Service for AudioService {
+    fn start(&self) {
+        log::info!("Start {:?} audio", self.audio_type);
+    }
+
+    fn stop(&self) {
+        log::info!("Stop {:?} audio", self.audio_type);
+    }
+
+    fn pause(&self) {
+        log::info!("Pause {:?} audio", self.audio_type);
+    }
+
+    fn resume(&self) {
+        log::info!("Resume {:?} audio", self.audio_type);
+    }
+
+    fn fill_features(&self, channel_id: ChannelID, response: &mut ServiceDiscoveryResponse) {
+        log::info!("Fill Features");
+
+        let mut channel_descriptor = crate::protos::ChannelDescriptorData::ChannelDescriptor::default();
+        channel_descriptor.set_channel_id(channel_id.into());
+        let mut av_channel = AVChannel::new();
+        av_channel.set_stream_type(avstream_type::Enum::AUDIO);
+        av_channel.set_audio_type(self.audio_type);
+        av_channel.set_available_while_in_call(true);
+        av_channel.audio_configs.push(self.config.clone());
+        channel_descriptor.av_channel = Some(av_channel).into();
+        log::debug!("{:?} audio channel descriptor: {:?}", self.audio_type, channel_descriptor);
+
+        response.channels.push(channel_descriptor);
+    }
+
+    fn channel_handler(&self) -> Option<Box<dyn ChannelHandler>> {
+        let mut channel = AudioServiceChannel::new(self.audio_type, self.config.clone(), self.max_unacked);
+        if let Some(sink) = &self.sink {
+            channel.set_sink(sink.clone());
+        }
+        Some(Box::new(channel))
+    }
+}
@@ -0,0 +1,80 @@
+use crate::channels::input_service_channel::InputServiceChannel;
+use crate::channels::ChannelHandler;
+use crate::input::keycode;
+use crate::messenger::ChannelID;
+use crate::services::service::Service;
+use crate::protos::ButtonCodeEnum::button_code;
+use crate::protos::InputChannelData::InputChannel;
+use crate::protos::ServiceDiscoveryResponseMessage::ServiceDiscoveryResponse;
+use crate::protos::TouchConfigData::TouchConfig;
+
+///Touch screen, buttons and knobs the phone's UI can be controlled with
+pub struct InputService {
+    touch_config: Option<TouchConfig>,
+    supported_keycodes: Vec<button_code::Enum>,
+}
+
+impl InputService {
+    ///Service without any input device
+    pub fn new() -> Self {
+        InputService { touch_config: None, supported_keycodes: Vec::new() }
+    }
+
+    ///Advertise a touch screen with the given resolution, usually the one of the video config
+    pub fn set_touch_screen(&mut self, width: u32, height: u32) {
+        let mut touch_config = TouchConfig::new();
+        touch_config.set_width(width);
+        touch_config.set_height(height);
+        self.touch_config = Some(touch_config);
+    }
+
+    ///Buttons and knobs of the head unit, SCROLL_WHEEL stands for a rotary knob
+    pub fn set_supported_keycodes(&mut self, supported_keycodes: &[button_code::Enum]) {
+        self.supported_keycodes = supported_keycodes.to_vec();
+    }
+}
+
+impl Default for InputService {
+    ///Touch screen matching the default video config of 800x480
+    fn default() -> Self {
+        let mut input_service = Self::new();
+        input_service.set_touch_screen(800, 480);
+        input_service
+    }
+}
+
+impl Service for InputService {
+    fn start(&self) {
+        log::info!("Start");
+    }
+
+    fn stop(&self) {
+        log::info!("Stop");
+    }
+
+    fn pause(&self) {
+        log::info!("Pause");
+    }
+
+    fn resume(&self) {
+        log::info!("Resume");
+    }
+
+    fn fill_features(&self, channel_id: ChannelID, response: &mut ServiceDiscoveryResponse) {
+        log::info!("Fill Features");
+
+        let mut channel_descriptor = crate::protos::ChannelDescriptorData::ChannelDescriptor::default();
+        channel_descriptor.set_channel_id(channel_id.into());
+        let mut input_channel = InputChannel::new();
+        input_channel.touch_screen_config = self.touch_config.clone().into();
+        input_channel.supported_keycodes = self.supported_keycodes.iter().map(|code| keycode(*code)).collect();
+        channel_descriptor.input_channel = Some(input_channel).into();
+        log::debug!("Input channel descriptor: {:?}", channel_descriptor);
+
+        response.channels.push(channel_descriptor);
+    }
+
+    fn channel_handler(&self) -> Option<Box<dyn ChannelHandler>> {
+        Some(Box::new(InputServiceChannel))
+    }
+}
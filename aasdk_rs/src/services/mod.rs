@@ -0,0 +1,11 @@
+pub mod service;
+pub mod service_registry;
+pub mod audio_input_service;
+pub mod audio_service;
+pub mod input_service;
+pub mod sensor_service;
+pub mod video_service;
+pub mod wifi_service;
+
+pub use self::service::Service;
+pub use self::service_registry::ServiceRegistry;
@@ -1,9 +1,12 @@
+use crate::channels::sensor_service_channel::SensorServiceChannel;
+use crate::channels::ChannelHandler;
+use crate::messenger::ChannelID;
 use crate::services::service::Service;
 use crate::protos::ServiceDiscoveryResponseMessage::ServiceDiscoveryResponse;
 
-pub struct AudioInputService {}
+pub struct SensorService {}
 
-impl Service for AudioInputService {
+impl Service for SensorService {
     fn start(&self) {
         log::info!("Start");
     }
@@ -20,15 +23,19 @@ impl Service for AudioInputService {
         log::info!("Resume");
     }
 
-    fn fill_features(&self, response: &mut ServiceDiscoveryResponse) {
+    fn fill_features(&self, channel_id: ChannelID, response: &mut ServiceDiscoveryResponse) {
         log::info!("Fill Features");
 
         let mut channel_descriptor = crate::protos::ChannelDescriptorData::ChannelDescriptor::default();
-        channel_descriptor.set_channel_id(crate::messenger::ChannelID::AVInput as u32);
+        channel_descriptor.set_channel_id(channel_id.into());
 
         dbg!(channel_descriptor.clone());
 
 
         response.channels.push(channel_descriptor);
     }
+
+    fn channel_handler(&self) -> Option<Box<dyn ChannelHandler>> {
+        Some(Box::new(SensorServiceChannel))
+    }
 }
@@ -0,0 +1,20 @@
+use crate::channels::ChannelHandler;
+use crate::messenger::ChannelID;
+use crate::protos::ServiceDiscoveryResponseMessage::ServiceDiscoveryResponse;
+
+///A head unit feature (video, audio, input, ...) advertised to the phone during service discovery
+pub trait Service {
+    fn start(&self);
+    fn stop(&self);
+    fn pause(&self);
+    fn resume(&self);
+    ///Add the channel descriptor of this service to the service discovery response,
+    ///using the channel id the service was assigned for this session
+    fn fill_features(&self, channel_id: ChannelID, response: &mut ServiceDiscoveryResponse);
+    ///Handler for the messages the phone sends on the channel of this service
+    ///
+    ///Services without a handler are only advertised, messages on their channel are rejected.
+    fn channel_handler(&self) -> Option<Box<dyn ChannelHandler>> {
+        None
+    }
+}
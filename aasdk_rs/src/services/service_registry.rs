@@ -0,0 +1,76 @@
+use crate::channels::ChannelRegistry;
+use crate::messenger::ChannelID;
+use crate::protos::ServiceDiscoveryResponseMessage::ServiceDiscoveryResponse;
+use crate::services::Service;
+
+struct RegisteredService {
+    service: Box<dyn Service>,
+    channel_id: Option<ChannelID>,
+}
+
+///Services of the head unit and the channel ids they were assigned in the current session
+#[derive(Default)]
+pub struct ServiceRegistry {
+    services: Vec<RegisteredService>,
+}
+
+impl ServiceRegistry {
+    pub fn new() -> Self {
+        ServiceRegistry { services: Vec::new() }
+    }
+
+    ///Add a service, it gets its channel id with the next service discovery
+    pub fn add(&mut self, service: Box<dyn Service>) {
+        self.services.push(RegisteredService { service, channel_id: None });
+    }
+
+    pub fn len(&self) -> usize {
+        self.services.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Service> {
+        self.services.iter().map(|registered| registered.service.as_ref())
+    }
+
+    ///Channel ids assigned during the last service discovery, in the order the services were added
+    pub fn channel_ids(&self) -> Vec<Option<ChannelID>> {
+        self.services.iter().map(|registered| registered.channel_id).collect()
+    }
+
+    ///Assign a channel id to every service, describe the services in the response
+    ///and route their channels to the service's channel handler
+    ///
+    ///Ids are handed out in the order the services were added, skipping ids that already have a
+    ///handler registered by the application. Services keep the id of a previous discovery, only
+    ///the handlers installed for them are dropped, so a handler the application replaced one with
+    ///stays in place.
+    pub fn assign_channels(&mut self, response: &mut ServiceDiscoveryResponse, channels: &mut ChannelRegistry) {
+        let previous_ids: Vec<Option<ChannelID>> = self.services.iter_mut().map(|registered| registered.channel_id.take()).collect();
+        for channel_id in previous_ids.iter().flatten() {
+            channels.remove_service(*channel_id);
+        }
+        let mut candidates = (1..=u8::MAX).map(ChannelID)
+            .filter(|channel_id| !previous_ids.contains(&Some(*channel_id)));
+        for (registered, previous_id) in self.services.iter_mut().zip(previous_ids.iter()) {
+            let channel_id = match previous_id.or_else(|| candidates.find(|channel_id| !channels.contains(*channel_id))) {
+                Some(channel_id) => channel_id,
+                None => {
+                    log::error!("No channel id left, not advertising the remaining services");
+                    break;
+                }
+            };
+            log::info!("Assigning channel {:?} to service", channel_id);
+            registered.service.fill_features(channel_id, response);
+            if channels.contains(channel_id) {
+                log::info!("Keeping the application's handler for channel {:?}", channel_id);
+            } else if let Some(handler) = registered.service.channel_handler() {
+                channels.register_service(channel_id, handler);
+            }
+            registered.channel_id = Some(channel_id);
+        }
+    }
+}
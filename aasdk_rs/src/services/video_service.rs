@@ -1,9 +1,44 @@
+use crate::channels::video_service_channel::{default_video_config, VideoServiceChannel, DEFAULT_VIDEO_MAX_UNACKED};
+use crate::channels::ChannelHandler;
+use crate::media::video::SharedVideoSink;
+use crate::messenger::ChannelID;
 use crate::services::service::Service;
+use crate::protos::AVChannelData::AVChannel;
+use crate::protos::AVStreamTypeEnum::avstream_type;
 use crate::protos::ServiceDiscoveryResponseMessage::ServiceDiscoveryResponse;
+use crate::protos::VideoConfigData::VideoConfig;
 
-pub struct InputService {}
+///Projection of the phone's screen
+pub struct VideoService {
+    config: VideoConfig,
+    max_unacked: u32,
+    sink: Option<SharedVideoSink>,
+}
+
+impl VideoService {
+    ///Service advertising `config` as the only video configuration
+    pub fn new(config: VideoConfig) -> Self {
+        VideoService { config, max_unacked: DEFAULT_VIDEO_MAX_UNACKED, sink: None }
+    }
+
+    ///Frames the phone may send before it has to wait for an acknowledgement
+    pub fn set_max_unacked(&mut self, max_unacked: u32) {
+        self.max_unacked = max_unacked;
+    }
+
+    ///Receiver of the projected stream, kept across sessions
+    pub fn set_sink(&mut self, sink: SharedVideoSink) {
+        self.sink = Some(sink);
+    }
+}
 
-impl Service for InputService {
+impl Default for VideoService {
+    fn default() -> Self {
+        Self::new(default_video_config())
+    }
+}
+
+impl Service for VideoService {
     fn start(&self) {
         log::info!("Start");
     }
@@ -20,15 +55,26 @@ impl Service for InputService {
         log::info!("Resume");
     }
 
-    fn fill_features(&self, response: &mut ServiceDiscoveryResponse) {
+    fn fill_features(&self, channel_id: ChannelID, response: &mut ServiceDiscoveryResponse) {
         log::info!("Fill Features");
 
         let mut channel_descriptor = crate::protos::ChannelDescriptorData::ChannelDescriptor::default();
-        channel_descriptor.set_channel_id(crate::messenger::ChannelID::Input as u32);
-
-        dbg!(channel_descriptor.clone());
-
+        channel_descriptor.set_channel_id(channel_id.into());
+        let mut av_channel = AVChannel::new();
+        av_channel.set_stream_type(avstream_type::Enum::VIDEO);
+        av_channel.set_available_while_in_call(true);
+        av_channel.video_configs.push(self.config.clone());
+        channel_descriptor.av_channel = Some(av_channel).into();
+        log::debug!("Video channel descriptor: {:?}", channel_descriptor);
 
         response.channels.push(channel_descriptor);
     }
+
+    fn channel_handler(&self) -> Option<Box<dyn ChannelHandler>> {
+        let mut channel = VideoServiceChannel::new(self.config.clone(), self.max_unacked);
+        if let Some(sink) = &self.sink {
+            channel.set_sink(sink.clone());
+        }
+        Some(Box::new(channel))
+    }
 }
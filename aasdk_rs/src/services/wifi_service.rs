@@ -1,9 +1,10 @@
+use crate::messenger::ChannelID;
 use crate::services::service::Service;
 use crate::protos::ServiceDiscoveryResponseMessage::ServiceDiscoveryResponse;
 
-pub struct InputService {}
+pub struct WifiService {}
 
-impl Service for InputService {
+impl Service for WifiService {
     fn start(&self) {
         log::info!("Start");
     }
@@ -20,11 +21,11 @@ impl Service for InputService {
         log::info!("Resume");
     }
 
-    fn fill_features(&self, response: &mut ServiceDiscoveryResponse) {
+    fn fill_features(&self, channel_id: ChannelID, response: &mut ServiceDiscoveryResponse) {
         log::info!("Fill Features");
 
         let mut channel_descriptor = crate::protos::ChannelDescriptorData::ChannelDescriptor::default();
-        channel_descriptor.set_channel_id(crate::messenger::ChannelID::Input as u32);
+        channel_descriptor.set_channel_id(channel_id.into());
 
         dbg!(channel_descriptor.clone());
 
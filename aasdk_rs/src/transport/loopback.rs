@@ -0,0 +1,61 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use crate::transport::{Transport, TransportError};
+
+///In-process duplex transport, used to drive the head unit without a phone attached
+///
+///Every buffer sent on one endpoint is received on the other endpoint with its boundaries
+///preserved, just like a single bulk transfer on USB. A read with a buffer smaller than the
+///pending transfer returns the rest on the next read. Closing an endpoint disconnects it, the
+///other endpoint reads what was sent before and then fails with Disconnected.
+pub struct LoopbackTransport {
+    tx: Option<Sender<Vec<u8>>>,
+    rx: Mutex<Receiver<Vec<u8>>>,
+    pending: Mutex<VecDeque<u8>>,
+}
+
+impl LoopbackTransport {
+    ///Create two connected endpoints, e.g. one for the head unit and one acting as the phone
+    pub fn pair() -> (LoopbackTransport, LoopbackTransport) {
+        let (a_tx, b_rx) = channel();
+        let (b_tx, a_rx) = channel();
+        let a = LoopbackTransport { tx: Some(a_tx), rx: Mutex::new(a_rx), pending: Mutex::new(VecDeque::new()) };
+        let b = LoopbackTransport { tx: Some(b_tx), rx: Mutex::new(b_rx), pending: Mutex::new(VecDeque::new()) };
+        (a, b)
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn send_buffer(&self, buffer: &[u8], _timeout: Duration) -> Result<usize, TransportError> {
+        let tx = self.tx.as_ref().ok_or(TransportError::Disconnected)?;
+        tx.send(buffer.to_vec()).map_err(|_| TransportError::Disconnected)?;
+        log::debug!("Loopback sent {} bytes", buffer.len());
+        Ok(buffer.len())
+    }
+
+    fn read_buffer(&self, buffer: &mut [u8], timeout: Duration) -> Result<usize, TransportError> {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.is_empty() {
+            let received = match self.rx.lock().unwrap().recv_timeout(timeout) {
+                Ok(received) => received,
+                Err(RecvTimeoutError::Timeout) => return Err(TransportError::Timeout),
+                Err(RecvTimeoutError::Disconnected) => return Err(TransportError::Disconnected),
+            };
+            pending.extend(received);
+        }
+        let size = buffer.len().min(pending.len());
+        for (target, byte) in buffer.iter_mut().zip(pending.drain(..size)) {
+            *target = byte;
+        }
+        log::debug!("Loopback read {} bytes", size);
+        Ok(size)
+    }
+
+    fn close(&mut self) -> Result<(), TransportError> {
+        self.tx = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+pub mod loopback;
+pub mod tcp;
+
+///Errors that can occur while moving raw bytes over a transport
+#[derive(Debug, thiserror::Error)]
+pub enum TransportError {
+    #[error("Transfer timed out")]
+    Timeout,
+    #[error("Transport disconnected")]
+    Disconnected,
+    #[error("USB error: {0}")]
+    Usb(rusb::Error),
+    #[error("IO error: {0}")]
+    Io(std::io::Error),
+}
+
+impl From<rusb::Error> for TransportError {
+    fn from(error: rusb::Error) -> Self {
+        match error {
+            rusb::Error::Timeout => TransportError::Timeout,
+            rusb::Error::NoDevice => TransportError::Disconnected,
+            _ => TransportError::Usb(error),
+        }
+    }
+}
+
+impl From<std::io::Error> for TransportError {
+    fn from(error: std::io::Error) -> Self {
+        use std::io::ErrorKind;
+        match error.kind() {
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => TransportError::Timeout,
+            ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe => TransportError::Disconnected,
+            _ => TransportError::Io(error),
+        }
+    }
+}
+
+///Raw byte transport between the head unit and the phone (USB bulk endpoints, sockets, ...)
+///
+///The messenger only ever talks to the phone through this trait, so framing and channel
+///handling do not depend on the underlying link.
+pub trait Transport {
+    ///Send the whole buffer, returning the number of bytes written
+    fn send_buffer(&self, buffer: &[u8], timeout: Duration) -> Result<usize, TransportError>;
+
+    ///Read up to `buffer.len()` bytes, returning the number of bytes actually read
+    fn read_buffer(&self, buffer: &mut [u8], timeout: Duration) -> Result<usize, TransportError>;
+
+    ///Give the link back at the end of a session, so the phone can connect again
+    fn close(&mut self) -> Result<(), TransportError> {
+        Ok(())
+    }
+}
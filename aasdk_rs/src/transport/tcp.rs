@@ -0,0 +1,71 @@
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
+
+use crate::transport::{Transport, TransportError};
+
+///Port the head unit listens on for wireless Android Auto connections
+pub const HEAD_UNIT_TCP_PORT: u16 = 5277;
+
+///Transport for wireless Android Auto, carrying the same frames as USB over a TCP stream
+pub struct TcpTransport {
+    stream: TcpStream,
+}
+
+impl TcpTransport {
+    ///Listen on the standard head unit port and wait for the phone to connect
+    pub fn listen() -> Result<Self, TransportError> {
+        let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, HEAD_UNIT_TCP_PORT)))?;
+        Self::accept(&listener)
+    }
+
+    ///Wait for the next phone connection on an already bound listener
+    pub fn accept(listener: &TcpListener) -> Result<Self, TransportError> {
+        log::info!("Waiting for phone connection on {:?}", listener.local_addr()?);
+        let (stream, address) = listener.accept()?;
+        log::info!("Accepted phone connection from {}", address);
+        Self::from_stream(stream).map_err(TransportError::from)
+    }
+
+    pub fn from_stream(stream: TcpStream) -> std::io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(TcpTransport { stream })
+    }
+
+    pub fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+}
+
+///`TcpStream` rejects a zero timeout, treat it as "block until done" like libusb does
+fn socket_timeout(timeout: Duration) -> Option<Duration> {
+    if timeout.is_zero() { None } else { Some(timeout) }
+}
+
+impl Transport for TcpTransport {
+    fn send_buffer(&self, buffer: &[u8], timeout: Duration) -> Result<usize, TransportError> {
+        self.stream.set_write_timeout(socket_timeout(timeout))?;
+        (&self.stream).write_all(buffer)?;
+        log::debug!("Sent {} bytes over TCP", buffer.len());
+        Ok(buffer.len())
+    }
+
+    fn read_buffer(&self, buffer: &mut [u8], timeout: Duration) -> Result<usize, TransportError> {
+        self.stream.set_read_timeout(socket_timeout(timeout))?;
+        match (&self.stream).read(buffer)? {
+            0 if !buffer.is_empty() => Err(TransportError::Disconnected),
+            size => {
+                log::debug!("Read {} bytes over TCP", size);
+                Ok(size)
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), TransportError> {
+        log::info!("Closing TCP connection");
+        match self.stream.shutdown(Shutdown::Both) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotConnected => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
@@ -3,11 +3,14 @@ use std::time::Duration;
 
 use rusb::{Device, GlobalContext};
 
+use crate::transport::{Transport, TransportError};
+
 pub struct UsbDriver {
     device: rusb::Device<GlobalContext>,
     handle: rusb::DeviceHandle<GlobalContext>,
     in_endpoint_addr: u8,
     out_endpoint_addr: u8,
+    claimed_interface: Option<u8>,
     timeout: Duration,
 }
 
@@ -20,6 +23,7 @@ impl UsbDriver {
         let mut handle = usb_device.open().unwrap();
         let mut interfaces = config_desc.interfaces();
         let num_interfaces = config_desc.num_interfaces();
+        let mut claimed_interface = None;
         if num_interfaces > 2 {
             log::error!("Too many interfaces found!");
         } else if num_interfaces == 0 {
@@ -27,6 +31,7 @@ impl UsbDriver {
         } else if num_interfaces == 2 {
             log::info!("Selecting AOA interface (0)");
             handle.claim_interface(0).unwrap();
+            claimed_interface = Some(0);
         }
         let aoa_interface = interfaces.nth(0).unwrap();
         let mut interface_desc = aoa_interface.descriptors().nth(0).unwrap();
@@ -39,20 +44,49 @@ impl UsbDriver {
             handle,
             in_endpoint_addr: endpoint_desc_in.address(),
             out_endpoint_addr: endpoint_desc_out.address(),
+            claimed_interface,
             timeout: Duration::from_secs(crate::constants::USB_TIMEOUT_SECONDS as u64),
         }
     }
 
-    pub fn send_buffer(&self, buffer: &[u8]) {
-        log::info!("Sent {} bits, result: {}",
-        buffer.len(),
-        self.handle.write_bulk(self.out_endpoint_addr, buffer, self.timeout).unwrap());
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+impl Transport for UsbDriver {
+    fn send_buffer(&self, buffer: &[u8], timeout: Duration) -> Result<usize, TransportError> {
+        //a bulk transfer may be cut short, keep going until the phone has the whole frame
+        let mut sent = 0;
+        while sent < buffer.len() {
+            let size = self.handle.write_bulk(self.out_endpoint_addr, &buffer[sent..], timeout)?;
+            if size == 0 {
+                return Err(TransportError::Disconnected);
+            }
+            sent += size;
+        }
+        log::info!("Sent {} bytes", sent);
+        Ok(sent)
+    }
+
+    fn read_buffer(&self, buf: &mut [u8], timeout: Duration) -> Result<usize, TransportError> {
+        match self.handle.read_bulk(self.in_endpoint_addr, buf, timeout) {
+            Ok(size) => {
+                log::info!("Successfully read {size} bytes from USB device");
+                Ok(size)
+            }
+            Err(e) => {
+                log::error!("Error reading from USB device: {e}");
+                Err(e.into())
+            }
+        }
     }
 
-    pub fn read_buffer(&self, buf: &mut [u8]) {
-        match self.handle.read_bulk(self.in_endpoint_addr, buf, self.timeout) {
-            Ok(size) => { log::info!("Successfully read {size} bits from USB device")}
-            Err(e) => { log::error!("Error reading from USB device: {e}") }
-        };
+    fn close(&mut self) -> Result<(), TransportError> {
+        if let Some(interface) = self.claimed_interface.take() {
+            log::info!("Releasing USB interface {}", interface);
+            self.handle.release_interface(interface)?;
+        }
+        Ok(())
     }
-}
\ No newline at end of file
+}
@@ -1,3 +1,5 @@
+use aasdk_rs::protos::AudioTypeEnum::audio_type;
+use aasdk_rs::services::audio_service::AudioService;
 use std::time::Duration;
 
 fn setup_logger() -> Result<(), fern::InitError> {
@@ -19,9 +21,35 @@ fn setup_logger() -> Result<(), fern::InitError> {
     Ok(())
 }
 
+///Load the head unit certificate and key, paths can be overridden through the environment
+fn load_identity() -> Result<aasdk_rs::cryptor::HeadUnitIdentity, aasdk_rs::cryptor::IdentityError> {
+    let certificate_path = std::env::var("RUSTYAUTO_CERTIFICATE").unwrap_or_else(|_| "headunit.crt".to_string());
+    let private_key_path = std::env::var("RUSTYAUTO_PRIVATE_KEY").unwrap_or_else(|_| "headunit.key".to_string());
+    aasdk_rs::cryptor::HeadUnitIdentity::from_pem_files(certificate_path, private_key_path)
+}
+
+///WAV capture of an audio channel, written into the directory named by RUSTYAUTO_AUDIO_CAPTURE
+fn audio_capture(channel_name: &str) -> Option<aasdk_rs::media::SharedAudioSink> {
+    let capture_path = std::path::Path::new(&std::env::var("RUSTYAUTO_AUDIO_CAPTURE").ok()?).join(format!("{}.wav", channel_name));
+    match aasdk_rs::media::WavFileSink::create(&capture_path) {
+        Ok(sink) => Some(std::rc::Rc::new(std::cell::RefCell::new(sink))),
+        Err(e) => {
+            log::error!("Unable to create audio capture {}: {}", capture_path.display(), e);
+            None
+        }
+    }
+}
+
 fn main() {
     setup_logger().unwrap();
     log::info!("Initialized Logging");
+    let identity = match load_identity() {
+        Ok(identity) => identity,
+        Err(e) => {
+            log::error!("Unable to load head unit certificate: {}", e);
+            return;
+        }
+    };
     let aoa_config = aoap_rs::AOAConfig {
         manufacturer: "Android".to_string(),
         model_name: "Android Auto".to_string(),
@@ -38,7 +66,47 @@ fn main() {
             let usb_driver = aasdk_rs::usbdriver::UsbDriver::init(device);
 
             let mut android_auto_entity = aasdk_rs::androidautoentity::AndroidAutoEntity::new(usb_driver);
-            android_auto_entity.start();
+            android_auto_entity.set_identity(identity);
+            let mut video_service = aasdk_rs::services::video_service::VideoService::default();
+            if let Ok(capture_path) = std::env::var("RUSTYAUTO_VIDEO_CAPTURE") {
+                match aasdk_rs::media::H264FileSink::create(&capture_path) {
+                    Ok(sink) => video_service.set_sink(std::rc::Rc::new(std::cell::RefCell::new(sink))),
+                    Err(e) => log::error!("Unable to create video capture {}: {}", capture_path, e),
+                }
+            }
+            android_auto_entity.add_service(Box::new(video_service));
+            let mut media_audio_service = AudioService::with_default_config(audio_type::Enum::MEDIA);
+            let mut speech_audio_service = AudioService::with_default_config(audio_type::Enum::SPEECH);
+            let mut system_audio_service = AudioService::with_default_config(audio_type::Enum::SYSTEM);
+            if let Some(sink) = audio_capture("media") {
+                media_audio_service.set_sink(sink);
+            }
+            if let Some(sink) = audio_capture("speech") {
+                speech_audio_service.set_sink(sink);
+            }
+            if let Some(sink) = audio_capture("system") {
+                system_audio_service.set_sink(sink);
+            }
+            android_auto_entity.add_service(Box::new(media_audio_service));
+            android_auto_entity.add_service(Box::new(speech_audio_service));
+            android_auto_entity.add_service(Box::new(system_audio_service));
+            let mut audio_input_service = aasdk_rs::services::audio_input_service::AudioInputService::default();
+            if let Ok(microphone_path) = std::env::var("RUSTYAUTO_MICROPHONE_WAV") {
+                match aasdk_rs::media::WavFileSource::open(&microphone_path) {
+                    Ok(source) => {
+                        audio_input_service = aasdk_rs::services::audio_input_service::AudioInputService::new(source.config().clone());
+                        audio_input_service.set_source(std::rc::Rc::new(std::cell::RefCell::new(source)));
+                    }
+                    Err(e) => log::error!("Unable to open microphone recording {}: {}", microphone_path, e),
+                }
+            }
+            android_auto_entity.add_service(Box::new(audio_input_service));
+            android_auto_entity.add_service(Box::new(aasdk_rs::services::sensor_service::SensorService {}));
+            android_auto_entity.add_service(Box::new(aasdk_rs::services::input_service::InputService::default()));
+            match android_auto_entity.start() {
+                Ok(()) => log::info!("Android Auto session ended"),
+                Err(e) => log::error!("Android Auto session failed: {}", e),
+            }
         }
         _ => log::error!("No compatible device found!"),
     };